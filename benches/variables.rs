@@ -0,0 +1,65 @@
+// Times variable-heavy loops on both backends. Run with `cargo bench`.
+use std::time::Instant;
+
+use interpreter::{Backend, Lox};
+
+const RUNS: u32 = 5;
+
+const PROGRAMS: [(&str, &str); 3] = [
+    ("locals in nested blocks", "
+var total = 0;
+for (var i = 0; i < 100000; i = i + 1) {
+  var a = i;
+  {
+    var b = a + 1;
+    {
+      var c = b * 2;
+      total = total + a + b - c;
+    }
+  }
+}
+"),
+    ("globals", "
+var a = 0;
+var b = 1;
+var c = 2;
+for (var i = 0; i < 100000; i = i + 1) {
+  a = b + c;
+  b = c + a;
+  c = a - b;
+}
+"),
+    ("closures over outer variables", "
+fun counter() {
+  var count = 0;
+  var step = 1;
+  fun next() {
+    count = count + step;
+    return count;
+  }
+  return next;
+}
+var next = counter();
+for (var i = 0; i < 50000; i = i + 1) next();
+"),
+];
+
+fn time(backend: Backend, source: &str) -> f64 {
+    let mut best = f64::MAX;
+    for _ in 0..RUNS {
+        let mut lox = Lox::with_backend(backend, Box::new(std::io::sink()), Box::new(std::io::sink()));
+        let start = Instant::now();
+        lox.run_source(source).unwrap();
+        best = best.min(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    best
+}
+
+fn main() {
+    println!("{:<32} {:>12} {:>12}", "program (best of 5)", "tree-walker", "vm");
+    for (name, source) in PROGRAMS {
+        let tree_walker = time(Backend::TreeWalker, source);
+        let vm = time(Backend::Vm, source);
+        println!("{:<32} {:>9.2} ms {:>9.2} ms", name, tree_walker, vm);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::class::LoxClass;
+use crate::environment::{Captures, Frame};
+use crate::generate_ast::LiteralsAst;
+use crate::interpreter::{Interpreter, RuntimeError, Unwind};
+use crate::parser::FunctionDecl;
+use crate::stdlib::NativeState;
+use std::fmt;
+use std::ptr;
+use std::rc::Rc;
+
+pub trait Callable {
+    fn arity(&self) -> usize;
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<LiteralsAst>) -> Result<LiteralsAst, RuntimeError>;
+}
+
+// The Rust side of a native function: it gets the running session's native
+// state and the evaluated arguments, already checked against the declared arity.
+pub type NativeFn = fn(&mut NativeState, &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError>;
+
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Callable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<LiteralsAst>) -> Result<LiteralsAst, RuntimeError> {
+        let result = (self.function)(&mut interpreter.natives, &arguments)?;
+        interpreter.heap.adopt(&result);
+        Ok(result)
+    }
+}
+
+pub struct LoxFunction {
+    pub declaration: Rc<FunctionDecl>,
+    // The locals of enclosing functions that the body uses, kept alive for as
+    // long as the function value is.
+    pub captures: Rc<Captures>,
+    pub is_initializer: bool,
+    // For methods, the instance `bind` attached and, in a subclass, the class
+    // `super` refers to. They take the first slots of each call's frame.
+    pub this: Option<LiteralsAst>,
+    pub superclass: Option<Rc<LoxClass>>,
+}
+
+impl LoxFunction {
+    // The same method with `this` bound to the given instance.
+    pub fn bind(&self, instance: LiteralsAst) -> LoxFunction {
+        LoxFunction {
+            declaration: Rc::clone(&self.declaration),
+            captures: Rc::clone(&self.captures),
+            is_initializer: self.is_initializer,
+            this: Some(instance),
+            superclass: self.superclass.clone(),
+        }
+    }
+}
+
+// The captures usually hold the function itself, so they are left out here to
+// keep debug output finite.
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoxFunction")
+            .field("name", &self.declaration.name.lexeme)
+            .field("params", &self.declaration.params)
+            .finish()
+    }
+}
+
+// Two function values are only equal when they are the same declaration at runtime.
+impl PartialEq for LoxFunction {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.lexeme)
+    }
+}
+
+impl Callable for LoxFunction {
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<LiteralsAst>) -> Result<LiteralsAst, RuntimeError> {
+        let mut frame = Frame::default();
+        if let Some(this) = &self.this {
+            frame.push(this.clone());
+        }
+        if let Some(superclass) = &self.superclass {
+            frame.push(LiteralsAst::Class(Rc::clone(superclass)));
+        }
+        for argument in arguments {
+            frame.push(argument);
+        }
+
+        let result = interpreter.execute_call(&self.declaration.body, frame, Rc::clone(&self.captures));
+
+        // An initializer always hands back the instance, even on a bare `return;`.
+        match result {
+            Ok(_) | Err(Unwind::Return(_)) if self.is_initializer => {
+                Ok(self.this.clone().unwrap_or(LiteralsAst::Null))
+            },
+            Ok(_) => Ok(LiteralsAst::Null),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(err)) => Err(err)
+        }
+    }
+}
@@ -0,0 +1,121 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use crate::callable::{Callable, LoxFunction};
+use crate::generate_ast::LiteralsAst;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::symbol::{self, Symbol};
+use crate::token::Token;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::ptr;
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub struct LoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<LoxClass>>,
+    pub methods: HashMap<Symbol, Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    // Methods are looked up on the class first, then up the superclass chain.
+    pub fn find_method(&self, name: &Symbol) -> Option<Rc<LoxFunction>> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(Rc::clone(method));
+        }
+        match &self.superclass {
+            Some(superclass) => superclass.find_method(name),
+            None => None
+        }
+    }
+}
+
+impl PartialEq for LoxClass {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// Calling a class constructs an instance, so the instance needs a handle on the
+// class itself rather than a borrowed `LoxClass`.
+impl Callable for Rc<LoxClass> {
+    fn arity(&self) -> usize {
+        match self.find_method(&symbol::init()) {
+            Some(initializer) => initializer.arity(),
+            None => 0
+        }
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<LiteralsAst>) -> Result<LiteralsAst, RuntimeError> {
+        let instance = LiteralsAst::Instance(Rc::new(RefCell::new(LoxInstance::new(Rc::clone(self)))));
+        interpreter.heap.track(&instance);
+        if let Some(initializer) = self.find_method(&symbol::init()) {
+            initializer.bind(instance.clone()).call(interpreter, arguments)?;
+        }
+        return Ok(instance);
+    }
+}
+
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    pub fields: HashMap<Symbol, LiteralsAst>,
+}
+
+// Fields can point back at the instance, so only their names are printed.
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoxInstance")
+            .field("class", &self.class.name)
+            .field("fields", &self.fields.keys())
+            .finish()
+    }
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        Self {
+            class: class,
+            fields: HashMap::new(),
+        }
+    }
+
+    // Fields shadow methods; a method found on the class comes back bound to
+    // this instance so `this` works inside it.
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Result<LiteralsAst, RuntimeError> {
+        if let Some(value) = instance.borrow().fields.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+
+        let method = instance.borrow().class.find_method(&name.lexeme);
+        match method {
+            Some(method) => {
+                let bound = method.bind(LiteralsAst::Instance(Rc::clone(instance)));
+                Ok(LiteralsAst::Callable(Rc::new(bound)))
+            },
+            None => Err(RuntimeError::new(name.clone(), format!("Undefined property '{}'.", name.lexeme)))
+        }
+    }
+
+    pub fn set(&mut self, name: &Token, value: LiteralsAst) {
+        self.fields.insert(name.lexeme.clone(), value);
+    }
+}
+
+impl PartialEq for LoxInstance {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
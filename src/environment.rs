@@ -1,74 +1,207 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
 use std::collections::HashMap;
 use std::rc::Rc;
-use crate::{token::Token};
-use crate::generate_ast::{Expr, LiteralsAst};
-use once_cell::sync::Lazy;
-use std::sync::{Arc, Mutex,};
-use lazy_static::lazy_static;
 use std::cell::RefCell;
-use std::sync::{RwLock};
-use std::borrow::BorrowMut;
+use crate::{token::Token};
+use crate::symbol::Symbol;
+use crate::generate_ast::LiteralsAst;
+use crate::interpreter::RuntimeError;
+use crate::trace::trace;
 
 
+// Global variables, keyed by interned symbol. Locals never land here: the
+// resolver gives each one a slot in its function's `Frame`.
 #[derive(Debug, Clone)]
 pub struct Environment {
-    pub enclosing: Option<Rc<Environment>>,
-    values: HashMap<String, LiteralsAst>
+    values: HashMap<Symbol, LiteralsAst>
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Environment {
     pub fn new() -> Self {
-        println!("New Environment");
+        trace!("New Environment");
         Self {
-            enclosing: None,
             values: HashMap::new(),
         }
     }
 
-    pub fn define(&mut self, name: String, value: LiteralsAst) {
-        // print!("define: {:?} {:?} \n", name, value);
+    pub fn define(&mut self, name: Symbol, value: LiteralsAst) {
         self.values.insert(name, value);
-        // println!("define store");
+    }
 
+    pub fn get(&self, name: &Symbol) -> Option<LiteralsAst> {
+        return self.values.get(name).cloned();
     }
 
-    pub fn get(&self, name: String) -> Option<&LiteralsAst> {
-        print!("get: {:?} \n", self.values);
+    pub fn assign(&mut self, name: &Token, value: LiteralsAst) -> Result<(), RuntimeError> {
+        if let Some(current) = self.values.get_mut(&name.lexeme) {
+            *current = value;
+            return Ok(());
+        }
+        Err(RuntimeError::new(name.clone(), format!("Undefined variable '{}'.", name.lexeme)))
+    }
+}
 
-        let value = self.values.get(&name);
+// Where the resolver found a local: in a slot of the running call's frame, or
+// among the cells the running function's closure captured. A function's list
+// of captures uses the same type, read from the function around it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Local {
+    Slot(usize),
+    Captured(usize)
+}
 
-        match (value, &self.enclosing) {
-            (Some(val), _) => {
-                Some(val)
-            },
-            (None, Some(env)) => {
-                env.get(name)
-            },
-            (None, None) => {
-                None
-            }
+// A local some closure has captured, shared between the frame and the closure.
+pub type Cell = Rc<RefCell<LiteralsAst>>;
+
+#[derive(Debug, Clone)]
+enum Slot {
+    Value(LiteralsAst),
+    Captured(Cell)
+}
+
+// The locals of one function call, in the order the resolver numbered them.
+// Blocks truncate it back on the way out, so it grows and shrinks like a stack.
+#[derive(Debug, Default)]
+pub struct Frame {
+    slots: Vec<Slot>
+}
+
+impl Frame {
+    pub fn len(&self) -> usize {
+        return self.slots.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.slots.is_empty();
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.slots.truncate(len);
+    }
+
+    pub fn push(&mut self, value: LiteralsAst) {
+        self.slots.push(Slot::Value(value));
+    }
+
+    // A new binding in `slot`. Closures that captured whatever was there
+    // before keep that, which is what gives each loop pass its own variable.
+    pub fn define(&mut self, slot: usize, value: LiteralsAst) {
+        if slot < self.slots.len() {
+            self.slots[slot] = Slot::Value(value);
+            return;
+        }
+        self.slots.resize(slot, Slot::Value(LiteralsAst::Null));
+        self.slots.push(Slot::Value(value));
+    }
+
+    pub fn get(&self, slot: usize) -> Option<LiteralsAst> {
+        match self.slots.get(slot)? {
+            Slot::Value(value) => Some(value.clone()),
+            Slot::Captured(cell) => Some(cell.borrow().clone())
         }
     }
 
-    pub fn assign(&mut self, name: Token, value: LiteralsAst) {
-        let name_lex = self.values.get(&name.lexeme);
+    // Updates the binding in `slot`, which closures that captured it see too.
+    pub fn assign(&mut self, slot: usize, value: LiteralsAst) -> bool {
+        match self.slots.get_mut(slot) {
+            Some(Slot::Value(current)) => *current = value,
+            Some(Slot::Captured(cell)) => *cell.borrow_mut() = value,
+            None => return false
+        }
+        return true;
+    }
 
-        match (name_lex, &self.enclosing) {
-            (Some(_), _) => {
-                // println!("assign store 1");
-                self.values.insert(name.lexeme.clone(), value);
-            },
-            (None, Some(env)) => {
-                // println!("assign store 2");
-                Rc::get_mut(&mut env.clone()).expect("Cannot get mut env").define(name.to_string(), value);
+    // Moves the local in `slot` into a cell, unless an earlier closure already
+    // did, so a closure can share it.
+    pub fn capture(&mut self, slot: usize) -> Cell {
+        if slot >= self.slots.len() {
+            self.define(slot, LiteralsAst::Null);
+        }
+        let captured = &mut self.slots[slot];
+        if let Slot::Value(value) = captured {
+            *captured = Slot::Captured(Rc::new(RefCell::new(std::mem::replace(value, LiteralsAst::Null))));
+        }
+        match captured {
+            Slot::Captured(cell) => Rc::clone(cell),
+            Slot::Value(_) => unreachable!("the slot was just captured")
+        }
+    }
+}
 
-            },
-            (None, None) => {
-                // println!("assign store None");
+// The locals a closure captured from the functions around it, in the order
+// the resolver listed them, and nothing else.
+#[derive(Debug, Default)]
+pub struct Captures {
+    cells: Vec<Cell>
+}
+
+impl Captures {
+    pub fn new(cells: Vec<Cell>) -> Rc<Captures> {
+        return Rc::new(Captures { cells: cells });
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        return &self.cells;
+    }
 
-                false;
-            }
+    pub fn cell(&self, index: usize) -> Option<&Cell> {
+        return self.cells.get(index);
+    }
+
+    pub fn get(&self, index: usize) -> Option<LiteralsAst> {
+        return Some(self.cells.get(index)?.borrow().clone());
+    }
+
+    pub fn assign(&self, index: usize, value: LiteralsAst) -> bool {
+        match self.cells.get(index) {
+            Some(cell) => {
+                *cell.borrow_mut() = value;
+                true
+            },
+            None => false
         }
-    }  
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_shares_until_redefined() {
+        let mut frame = Frame::default();
+        frame.define(0, LiteralsAst::Number(1.0));
+        let captures = Captures::new(vec![frame.capture(0)]);
+
+        frame.assign(0, LiteralsAst::Number(2.0));
+        assert_eq!(captures.get(0), Some(LiteralsAst::Number(2.0)));
+
+        // A fresh binding, as on the next pass through a loop body.
+        frame.define(0, LiteralsAst::Number(3.0));
+        assert_eq!(captures.get(0), Some(LiteralsAst::Number(2.0)));
+        assert_eq!(frame.get(0), Some(LiteralsAst::Number(3.0)));
+    }
+
+    #[test]
+    fn test_capture_boxes_only_the_captured_slot() {
+        let mut frame = Frame::default();
+        frame.define(0, LiteralsAst::Number(1.0));
+        frame.define(1, LiteralsAst::Number(2.0));
+        let first = frame.capture(1);
+        let second = frame.capture(1);
+        assert!(Rc::ptr_eq(&first, &second));
+        assert!(matches!(frame.slots[0], Slot::Value(_)));
+
+        let captures = Captures::new(vec![first]);
+        assert!(captures.assign(0, LiteralsAst::Number(5.0)));
+        assert_eq!(frame.get(1), Some(LiteralsAst::Number(5.0)));
+        assert_eq!(captures.get(1), None);
+    }
 }
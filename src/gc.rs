@@ -0,0 +1,572 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use crate::callable::LoxFunction;
+use crate::class::{LoxClass, LoxInstance};
+use crate::environment::{Captures, Cell};
+use crate::generate_ast::LiteralsAst;
+use crate::map::{LoxMap, MapKey};
+use crate::symbol::Symbol;
+use crate::vm::object::{BoundMethod, Class, Closure, Instance, Object, Upvalue};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem::{size_of, size_of_val};
+use std::rc::{Rc, Weak};
+
+// The first collection happens once this many bytes have been allocated; after
+// that the threshold is a multiple of what survived the last one.
+const FIRST_GC: usize = 1024 * 1024;
+const GROWTH: usize = 2;
+
+// A heap object, held strongly while the collector looks at it.
+#[derive(Clone)]
+enum Node {
+    List(Rc<RefCell<Vec<LiteralsAst>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    Instance(Rc<RefCell<Instance>>),
+    Class(Rc<RefCell<Class>>),
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+    // The tree-walker's objects.
+    Function(Rc<LoxFunction>),
+    LoxClass(Rc<LoxClass>),
+    LoxInstance(Rc<RefCell<LoxInstance>>),
+    Cell(Cell),
+    Captures(Rc<Captures>)
+}
+
+impl Node {
+    fn of(value: &LiteralsAst) -> Option<Node> {
+        match value {
+            LiteralsAst::List(items) => Some(Node::List(Rc::clone(items))),
+            LiteralsAst::Map(map) => Some(Node::Map(Rc::clone(map))),
+            LiteralsAst::Object(Object::Instance(instance)) => Some(Node::Instance(Rc::clone(instance))),
+            LiteralsAst::Object(Object::Class(class)) => Some(Node::Class(Rc::clone(class))),
+            LiteralsAst::Object(Object::Closure(closure)) => Some(Node::Closure(Rc::clone(closure))),
+            LiteralsAst::Object(Object::BoundMethod(method)) => Some(Node::BoundMethod(Rc::clone(method))),
+            LiteralsAst::Callable(function) => Some(Node::Function(Rc::clone(function))),
+            LiteralsAst::Class(class) => Some(Node::LoxClass(Rc::clone(class))),
+            LiteralsAst::Instance(instance) => Some(Node::LoxInstance(Rc::clone(instance))),
+            _ => None
+        }
+    }
+
+    // Identifies the object for as long as it is alive.
+    fn address(&self) -> usize {
+        match self {
+            Node::List(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Map(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Instance(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Class(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Closure(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::BoundMethod(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Upvalue(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Function(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::LoxClass(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::LoxInstance(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Cell(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Captures(rc) => Rc::as_ptr(rc) as *const () as usize
+        }
+    }
+
+    // Counts this handle too.
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::List(rc) => Rc::strong_count(rc),
+            Node::Map(rc) => Rc::strong_count(rc),
+            Node::Instance(rc) => Rc::strong_count(rc),
+            Node::Class(rc) => Rc::strong_count(rc),
+            Node::Closure(rc) => Rc::strong_count(rc),
+            Node::BoundMethod(rc) => Rc::strong_count(rc),
+            Node::Upvalue(rc) => Rc::strong_count(rc),
+            Node::Function(rc) => Rc::strong_count(rc),
+            Node::LoxClass(rc) => Rc::strong_count(rc),
+            Node::LoxInstance(rc) => Rc::strong_count(rc),
+            Node::Cell(rc) => Rc::strong_count(rc),
+            Node::Captures(rc) => Rc::strong_count(rc)
+        }
+    }
+
+    fn downgrade(&self) -> Handle {
+        match self {
+            Node::List(rc) => Handle::List(Rc::downgrade(rc)),
+            Node::Map(rc) => Handle::Map(Rc::downgrade(rc)),
+            Node::Instance(rc) => Handle::Instance(Rc::downgrade(rc)),
+            Node::Class(rc) => Handle::Class(Rc::downgrade(rc)),
+            Node::Closure(rc) => Handle::Closure(Rc::downgrade(rc)),
+            Node::BoundMethod(rc) => Handle::BoundMethod(Rc::downgrade(rc)),
+            Node::Upvalue(rc) => Handle::Upvalue(Rc::downgrade(rc)),
+            Node::Function(rc) => Handle::Function(Rc::downgrade(rc)),
+            Node::LoxClass(rc) => Handle::LoxClass(Rc::downgrade(rc)),
+            Node::LoxInstance(rc) => Handle::LoxInstance(Rc::downgrade(rc)),
+            Node::Cell(rc) => Handle::Cell(Rc::downgrade(rc)),
+            Node::Captures(rc) => Handle::Captures(Rc::downgrade(rc))
+        }
+    }
+
+    // Calls `visit` with every heap object this one refers to directly.
+    fn children(&self, visit: &mut dyn FnMut(Node)) {
+        let mut value = |value: &LiteralsAst| {
+            if let Some(node) = Node::of(value) {
+                visit(node);
+            }
+        };
+        match self {
+            Node::List(items) => items.borrow().iter().for_each(value),
+            Node::Map(map) => map.borrow().iter().for_each(|(_, entry)| value(entry)),
+            Node::Instance(instance) => {
+                let instance = instance.borrow();
+                value(&LiteralsAst::Object(Object::Class(Rc::clone(&instance.class))));
+                instance.fields.values().for_each(value);
+            },
+            Node::Class(class) => {
+                for method in class.borrow().methods.values() {
+                    value(&LiteralsAst::Object(Object::Closure(Rc::clone(method))));
+                }
+            },
+            Node::Closure(closure) => {
+                for upvalue in &closure.upvalues {
+                    visit(Node::Upvalue(Rc::clone(upvalue)));
+                }
+            },
+            Node::BoundMethod(method) => {
+                value(&method.receiver);
+                value(&LiteralsAst::Object(Object::Closure(Rc::clone(&method.method))));
+            },
+            Node::Upvalue(upvalue) => {
+                // An open upvalue points into the stack, which is a root anyway.
+                if let Upvalue::Closed(closed) = &*upvalue.borrow() {
+                    value(closed);
+                }
+            },
+            Node::Function(function) => {
+                if let Some(this) = &function.this {
+                    value(this);
+                }
+                if let Some(superclass) = &function.superclass {
+                    value(&LiteralsAst::Class(Rc::clone(superclass)));
+                }
+                visit(Node::Captures(Rc::clone(&function.captures)));
+            },
+            Node::LoxClass(class) => {
+                for method in class.methods.values() {
+                    visit(Node::Function(Rc::clone(method)));
+                }
+                if let Some(superclass) = &class.superclass {
+                    visit(Node::LoxClass(Rc::clone(superclass)));
+                }
+            },
+            Node::LoxInstance(instance) => {
+                let instance = instance.borrow();
+                value(&LiteralsAst::Class(Rc::clone(&instance.class)));
+                instance.fields.values().for_each(value);
+            },
+            Node::Cell(cell) => value(&cell.borrow()),
+            Node::Captures(captures) => {
+                for cell in captures.cells() {
+                    visit(Node::Cell(Rc::clone(cell)));
+                }
+            }
+        }
+    }
+
+    // A rough size, taken when the object is first tracked.
+    fn size(&self) -> usize {
+        let value = size_of::<LiteralsAst>();
+        match self {
+            Node::List(items) => size_of::<RefCell<Vec<LiteralsAst>>>() + items.borrow().capacity() * value,
+            Node::Map(map) => size_of::<RefCell<LoxMap>>() + map.borrow().len() * (size_of::<MapKey>() + value + size_of::<usize>()),
+            Node::Instance(instance) => size_of::<RefCell<Instance>>() + instance.borrow().fields.len() * (size_of::<Symbol>() + value),
+            Node::Class(class) => size_of::<RefCell<Class>>() + class.borrow().methods.len() * (size_of::<Symbol>() + size_of::<Rc<Closure>>()),
+            Node::Closure(closure) => size_of::<Closure>() + closure.upvalues.len() * size_of::<Rc<RefCell<Upvalue>>>(),
+            Node::BoundMethod(_) => size_of::<BoundMethod>(),
+            Node::Upvalue(_) => size_of::<RefCell<Upvalue>>(),
+            Node::Function(_) => size_of::<LoxFunction>(),
+            Node::LoxClass(class) => size_of::<LoxClass>() + class.methods.len() * (size_of::<Symbol>() + size_of::<Rc<LoxFunction>>()),
+            Node::LoxInstance(instance) => size_of::<RefCell<LoxInstance>>() + instance.borrow().fields.len() * (size_of::<Symbol>() + value),
+            Node::Cell(_) => size_of::<RefCell<LiteralsAst>>(),
+            Node::Captures(captures) => size_of::<Captures>() + size_of_val(captures.cells())
+        }
+    }
+
+    // Drops everything the object refers to. Garbage is only ever kept alive
+    // by a cycle, and this breaks it so reference counting can free the rest.
+    fn clear(&self) {
+        match self {
+            Node::List(items) => items.borrow_mut().clear(),
+            Node::Map(map) => map.borrow_mut().clear(),
+            Node::Instance(instance) => instance.borrow_mut().fields.clear(),
+            Node::Class(class) => class.borrow_mut().methods.clear(),
+            Node::Upvalue(upvalue) => *upvalue.borrow_mut() = Upvalue::Closed(LiteralsAst::Null),
+            Node::LoxInstance(instance) => instance.borrow_mut().fields.clear(),
+            Node::Cell(cell) => *cell.borrow_mut() = LiteralsAst::Null,
+            // Immutable; any cycle through them also runs through one of the above.
+            Node::Closure(_) | Node::BoundMethod(_) | Node::Function(_) | Node::LoxClass(_) | Node::Captures(_) => {}
+        }
+    }
+}
+
+// How the heap remembers an object without keeping it alive.
+enum Handle {
+    List(Weak<RefCell<Vec<LiteralsAst>>>),
+    Map(Weak<RefCell<LoxMap>>),
+    Instance(Weak<RefCell<Instance>>),
+    Class(Weak<RefCell<Class>>),
+    Closure(Weak<Closure>),
+    BoundMethod(Weak<BoundMethod>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+    Function(Weak<LoxFunction>),
+    LoxClass(Weak<LoxClass>),
+    LoxInstance(Weak<RefCell<LoxInstance>>),
+    Cell(Weak<RefCell<LiteralsAst>>),
+    Captures(Weak<Captures>)
+}
+
+impl Handle {
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Handle::List(weak) => weak.upgrade().map(Node::List),
+            Handle::Map(weak) => weak.upgrade().map(Node::Map),
+            Handle::Instance(weak) => weak.upgrade().map(Node::Instance),
+            Handle::Class(weak) => weak.upgrade().map(Node::Class),
+            Handle::Closure(weak) => weak.upgrade().map(Node::Closure),
+            Handle::BoundMethod(weak) => weak.upgrade().map(Node::BoundMethod),
+            Handle::Upvalue(weak) => weak.upgrade().map(Node::Upvalue),
+            Handle::Function(weak) => weak.upgrade().map(Node::Function),
+            Handle::LoxClass(weak) => weak.upgrade().map(Node::LoxClass),
+            Handle::LoxInstance(weak) => weak.upgrade().map(Node::LoxInstance),
+            Handle::Cell(weak) => weak.upgrade().map(Node::Cell),
+            Handle::Captures(weak) => weak.upgrade().map(Node::Captures)
+        }
+    }
+}
+
+// What an engine can reach directly. For the VM that is its stack, globals,
+// frames and open upvalues; the tree-walker's callers keep their frames on the
+// Rust stack, so it leaves finding those to the collector.
+#[derive(Default)]
+pub struct Roots {
+    nodes: Vec<Node>
+}
+
+impl Roots {
+    pub fn value(&mut self, value: &LiteralsAst) {
+        if let Some(node) = Node::of(value) {
+            self.nodes.push(node);
+        }
+    }
+
+    pub fn closure(&mut self, closure: &Rc<Closure>) {
+        self.nodes.push(Node::Closure(Rc::clone(closure)));
+    }
+
+    pub fn upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        self.nodes.push(Node::Upvalue(Rc::clone(upvalue)));
+    }
+}
+
+// The outcome of one collection, in estimated bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Collection {
+    pub before: usize,
+    pub after: usize,
+    pub next_gc: usize
+}
+
+impl Collection {
+    pub fn freed(&self) -> usize {
+        return self.before - self.after;
+    }
+}
+
+// An engine's managed heap: every list, map, instance, class, closure and
+// captured variable the engine creates is tracked here. Reference counting
+// frees most of them; a mark-and-sweep collection finds the ones only kept
+// alive by cycles and breaks those cycles.
+pub struct Heap {
+    // Keyed by address, with the size each object was tracked at.
+    objects: HashMap<usize, (Handle, usize)>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    allocated_since_gc: bool,
+    // Collect at every chance rather than when the threshold is reached, to
+    // shake out objects the engine forgot to root.
+    pub stress: bool,
+    // Report each collection on the engine's error sink.
+    pub log: bool
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: HashMap::new(),
+            bytes_allocated: 0,
+            next_gc: FIRST_GC,
+            allocated_since_gc: false,
+            stress: false,
+            log: false
+        }
+    }
+
+    // Starts tracking a newly created object. Anything that isn't a heap
+    // object is ignored.
+    pub fn track(&mut self, value: &LiteralsAst) {
+        if let Some(node) = Node::of(value) {
+            self.track_node(node);
+        }
+    }
+
+    pub fn track_upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        self.track_node(Node::Upvalue(Rc::clone(upvalue)));
+    }
+
+    // Tracks the cells a tree-walker closure captured along with the list
+    // that holds them.
+    pub fn track_captures(&mut self, captures: &Rc<Captures>) {
+        for cell in captures.cells() {
+            self.track_node(Node::Cell(Rc::clone(cell)));
+        }
+        self.track_node(Node::Captures(Rc::clone(captures)));
+    }
+
+    // Tracks the objects a native function created. New ones are only held by
+    // the native's result, so anything shared with the rest of the program
+    // is left alone, already tracked or not the engine's to manage.
+    pub fn adopt(&mut self, value: &LiteralsAst) {
+        if let Some(node) = Node::of(value) {
+            self.adopt_node(node);
+        }
+    }
+
+    fn adopt_node(&mut self, node: Node) {
+        // One reference from whatever holds it, one from `node`.
+        if node.strong_count() > 2 {
+            return;
+        }
+        node.children(&mut |child| self.adopt_node(child));
+        self.track_node(node);
+    }
+
+    fn track_node(&mut self, node: Node) {
+        let address = node.address();
+        if let Some((handle, size)) = self.objects.get(&address) {
+            if handle.upgrade().is_some() {
+                return;
+            }
+            // The address belonged to an object that has since been freed.
+            self.bytes_allocated -= size;
+        }
+        let size = node.size();
+        self.bytes_allocated += size;
+        self.allocated_since_gc = true;
+        self.objects.insert(address, (node.downgrade(), size));
+    }
+
+    pub fn should_collect(&self) -> bool {
+        return (self.stress && self.allocated_since_gc) || self.bytes_allocated > self.next_gc;
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        return self.bytes_allocated;
+    }
+
+    // Marks everything reachable from `roots`, then clears whatever tracked
+    // objects are left unmarked.
+    pub fn collect(&mut self, roots: Roots) -> Collection {
+        let before = self.bytes_allocated;
+        let mut marked = HashSet::new();
+        mark(roots.nodes, &mut marked);
+
+        // Something outside the heap may still hold an unmarked object, such
+        // as an embedder keeping a value it read from a global. Any reference
+        // an object has beyond those from other unmarked objects comes from
+        // outside, which makes it a root as well.
+        let mut internal: HashMap<usize, usize> = HashMap::new();
+        for (handle, _) in self.objects.values() {
+            if let Some(node) = handle.upgrade() {
+                if !marked.contains(&node.address()) {
+                    node.children(&mut |child| *internal.entry(child.address()).or_insert(0) += 1);
+                }
+            }
+        }
+        let mut held_outside = Vec::new();
+        for (handle, _) in self.objects.values() {
+            if let Some(node) = handle.upgrade() {
+                let address = node.address();
+                // Less one for `node` itself.
+                if !marked.contains(&address) && node.strong_count() - 1 > internal.get(&address).copied().unwrap_or(0) {
+                    held_outside.push(node);
+                }
+            }
+        }
+        mark(held_outside, &mut marked);
+
+        let mut freed = 0;
+        let mut survivors = HashMap::new();
+        for (address, (handle, size)) in self.objects.drain() {
+            match handle.upgrade() {
+                Some(_) if marked.contains(&address) => {
+                    survivors.insert(address, (handle, size));
+                },
+                Some(node) => {
+                    node.clear();
+                    freed += size;
+                },
+                // Already freed by reference counting.
+                None => freed += size
+            }
+        }
+        self.objects = survivors;
+        self.bytes_allocated -= freed;
+        self.next_gc = FIRST_GC.max(self.bytes_allocated * GROWTH);
+        self.allocated_since_gc = false;
+        return Collection { before: before, after: self.bytes_allocated, next_gc: self.next_gc };
+    }
+}
+
+fn mark(mut gray: Vec<Node>, marked: &mut HashSet<usize>) {
+    while let Some(node) = gray.pop() {
+        if marked.insert(node.address()) {
+            node.children(&mut |child| gray.push(child));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_ast::LiteralsAst;
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+    use crate::symbol::Symbol;
+    use crate::test_support::Captured;
+    use crate::vm::Vm;
+    use crate::{Backend, Lox};
+    use crate::vm::compiler::Compiler;
+    use crate::vm::object::Object;
+    use std::rc::Rc;
+
+    fn run(vm: &mut Vm, source: &str) {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let mut statements = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        vm.interpret(Compiler::new().compile(&statements).unwrap()).unwrap();
+    }
+
+    fn run_tree_walker(interpreter: &mut Interpreter, source: &str) {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let mut statements = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        interpreter.interpret(&statements).unwrap();
+    }
+
+    fn stressed_vm() -> Vm {
+        let mut vm = Vm::with_outputs(Box::new(Captured::default()), Box::new(Captured::default()));
+        vm.heap.stress = true;
+        return vm;
+    }
+
+    #[test]
+    fn test_frees_unreachable_cycles() {
+        let mut vm = stressed_vm();
+        run(&mut vm, "var l = []; push(l, l); var f; { fun g() { return g; } f = g; }");
+        let list = match &vm.globals[&Symbol::intern("l")] {
+            LiteralsAst::List(items) => Rc::downgrade(items),
+            _ => panic!("l should be a list")
+        };
+        let closure = match &vm.globals[&Symbol::intern("f")] {
+            LiteralsAst::Object(Object::Closure(closure)) => Rc::downgrade(closure),
+            _ => panic!("f should be a closure")
+        };
+        let allocated = vm.heap.bytes_allocated();
+
+        // The next allocation sets off a collection.
+        run(&mut vm, "l = nil; f = nil; var keep = [1];");
+        assert!(list.upgrade().is_none());
+        assert!(closure.upgrade().is_none());
+        assert!(vm.heap.bytes_allocated() < allocated);
+        assert_eq!(vm.globals[&Symbol::intern("keep")].to_string(), "[1]");
+    }
+
+    #[test]
+    fn test_tree_walker_frees_unreachable_cycles() {
+        let mut interpreter = Interpreter::with_outputs(Box::new(Captured::default()), Box::new(Captured::default()));
+        interpreter.heap.stress = true;
+        run_tree_walker(&mut interpreter, "var f; { fun g() { return g; } f = g; }
+            class A { m() {} } var a = A(); a.m = a.m;");
+        let closure = match interpreter.globals.get(&Symbol::intern("f")) {
+            Some(LiteralsAst::Callable(function)) => Rc::downgrade(&function),
+            _ => panic!("f should be a function")
+        };
+        let instance = match interpreter.globals.get(&Symbol::intern("a")) {
+            Some(LiteralsAst::Instance(instance)) => Rc::downgrade(&instance),
+            _ => panic!("a should be an instance")
+        };
+        let allocated = interpreter.heap.bytes_allocated();
+
+        // Collections happen between statements.
+        run_tree_walker(&mut interpreter, "f = nil; a = nil; var keep = [1]; keep;");
+        assert!(closure.upgrade().is_none());
+        assert!(instance.upgrade().is_none());
+        assert!(interpreter.heap.bytes_allocated() < allocated);
+        assert_eq!(interpreter.globals.get(&Symbol::intern("keep")).unwrap().to_string(), "[1]");
+    }
+
+    // A list or map that holds itself prints and compares, and is freed once
+    // nothing else refers to it.
+    #[test]
+    fn test_collects_self_containing_lists_and_maps() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let output = Captured::default();
+            let mut lox = Lox::with_backend(backend, Box::new(output.clone()), Box::new(Captured::default()));
+            lox.set_gc_stress(true);
+            lox.run_source("var l = [1]; push(l, l); var m = {}; m[\"m\"] = m; print l; print m == m;").unwrap();
+            let list = match lox.get_global("l") {
+                Some(LiteralsAst::List(items)) => Rc::downgrade(&items),
+                _ => panic!("l should be a list")
+            };
+            let map = match lox.get_global("m") {
+                Some(LiteralsAst::Map(map)) => Rc::downgrade(&map),
+                _ => panic!("m should be a map")
+            };
+
+            lox.run_source("l = nil; m = nil; var keep = [1]; keep;").unwrap();
+            assert!(list.upgrade().is_none(), "{:?}", backend);
+            assert!(map.upgrade().is_none(), "{:?}", backend);
+            assert_eq!(output.contents(), "[1, [...]]\ntrue\n");
+        }
+    }
+
+    #[test]
+    fn test_keeps_objects_held_outside_the_vm() {
+        let mut vm = stressed_vm();
+        run(&mut vm, "var l = [1]; push(l, l);");
+        let held = vm.globals[&Symbol::intern("l")].clone();
+        run(&mut vm, "l = nil; var other = [];");
+        match &held {
+            LiteralsAst::List(items) => assert_eq!(items.borrow().len(), 2),
+            _ => panic!("l should be a list")
+        }
+    }
+
+    #[test]
+    fn test_log_reports_each_collection() {
+        let log = Captured::default();
+        let mut vm = Vm::with_outputs(Box::new(Captured::default()), Box::new(log.clone()));
+        vm.heap.stress = true;
+        vm.heap.log = true;
+        run(&mut vm, "var a = [1]; a = nil; var b = [2];");
+        let lines: Vec<String> = log.contents().lines().map(|line| line.to_string()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("-- gc: collected 0 bytes (from "), "{}", lines[0]);
+        assert!(!lines[1].starts_with("-- gc: collected 0 bytes"), "{}", lines[1]);
+        assert!(lines[1].ends_with("next at 1048576"), "{}", lines[1]);
+    }
+}
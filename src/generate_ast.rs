@@ -1,17 +1,32 @@
-use crate::environment::{self, Environment};
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use crate::callable::{Callable, LoxFunction, NativeFunction};
+use crate::class::{LoxClass, LoxInstance};
+use crate::environment::Local;
+use crate::map::{LoxMap, MapKey};
+use crate::interpreter::{Interpreter, RuntimeError};
 use crate::{token::Token, scanner::*};
-use std::env;
-use std::fmt::format;
-use std::{collections::btree_map::Values, fmt};
+use crate::trace::trace;
+use crate::vm::object::Object;
+use std::fmt;
 use std::ops::Neg;
-use std::sync::{Arc, Mutex};
+use std::rc::Rc;
+use std::cell::RefCell;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum LiteralsAst {
     Number(f64),
-    Strings(String),
+    Strings(Rc<str>),
     True,
     False,
+    Callable(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<LiteralsAst>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    // Something only the bytecode VM creates, opaque to the tree-walker.
+    Object(Object),
     Null
 }
 
@@ -34,116 +49,331 @@ impl fmt::Display for LiteralsAst {
             LiteralsAst::Strings(x) => x.to_string(),
             LiteralsAst::True => true.to_string(),
             LiteralsAst::False => false.to_string(),
+            LiteralsAst::Callable(function) => function.to_string(),
+            LiteralsAst::Native(function) => function.to_string(),
+            LiteralsAst::Class(class) => class.to_string(),
+            LiteralsAst::Instance(instance) => instance.borrow().to_string(),
+            LiteralsAst::List(_) | LiteralsAst::Map(_) => self.describe(&mut Vec::new()),
+            LiteralsAst::Object(object) => object.to_string(),
             LiteralsAst::Null => "nil".to_string()
         };
         write!(f, "{}", description)
     }
 }
 
+// Lists and maps are equal when their contents are. Everything else that
+// lives behind an `Rc` is only equal to itself.
+impl PartialEq for LiteralsAst {
+    fn eq(&self, other: &Self) -> bool {
+        return self.equals(other, &mut Vec::new());
+    }
+}
+
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
-    Assign {name: Token, value: Box<Expr>},
+    Assign {name: Token, value: Box<Expr>, local: Option<Local>},
     Binary { left: Box<Expr>, operator: Token, right: Box<Expr>},
+    Call {callee: Box<Expr>, paren: Token, arguments: Vec<Expr>},
+    Get {object: Box<Expr>, name: Token},
     Grouping {expression: Box<Expr>},
+    Index {object: Box<Expr>, bracket: Token, index: Box<Expr>},
+    List {elements: Vec<Expr>},
+    Map {brace: Token, entries: Vec<(Expr, Expr)>},
     Literal {value: LiteralsAst},
     Logical {left: Box<Expr>, operator: Token, right: Box<Expr>},
+    Set {object: Box<Expr>, name: Token, value: Box<Expr>},
+    SetIndex {object: Box<Expr>, bracket: Token, index: Box<Expr>, value: Box<Expr>},
+    Slice {object: Box<Expr>, bracket: Token, start: Box<Expr>, end: Box<Expr>},
+    // Converts any value to its printed form; interpolated strings use it.
+    Stringify {expression: Box<Expr>},
+    // `local` finds the superclass and `this` the receiver to bind to.
+    Super {keyword: Token, method: Token, local: Option<Local>, this: Option<Local>},
+    This {keyword: Token, local: Option<Local>},
     Unary {operator: Token, right: Box<Expr>},
-    Variable {name: Token},
+    Variable {name: Token, local: Option<Local>},
     Null
 }
 
 impl LiteralsAst {
     pub fn is_truthy(&self) -> bool {
         match self {
-            LiteralsAst::Number(x) => {
-                if *x == 0 as f64{
-                    false
-                } else {
-                    true
+            LiteralsAst::Number(x) => *x != 0.0,
+            LiteralsAst::Strings(x) => !x.is_empty(),
+            LiteralsAst::True => true,
+            LiteralsAst::False => false,
+            LiteralsAst::Callable(_) => true,
+            LiteralsAst::Native(_) => true,
+            LiteralsAst::Class(_) => true,
+            LiteralsAst::Instance(_) => true,
+            LiteralsAst::List(_) => true,
+            LiteralsAst::Map(_) => true,
+            LiteralsAst::Object(_) => true,
+            LiteralsAst::Null => false
+        }
+    }
+
+    // The printed form, with `printing` holding the addresses of the lists and
+    // maps already being printed further out. A list or map that contains
+    // itself prints the inner copy as `[...]` or `{...}`.
+    pub(crate) fn describe(&self, printing: &mut Vec<usize>) -> String {
+        match self {
+            LiteralsAst::List(items) => {
+                let address = items.as_ptr() as *const () as usize;
+                if printing.contains(&address) {
+                    return "[...]".to_string();
                 }
+                printing.push(address);
+                let items: Vec<String> = items.borrow().iter().map(|item| item.describe(printing)).collect();
+                printing.pop();
+                return format!("[{}]", items.join(", "));
             },
-            LiteralsAst::Strings(x) => {
-                if x.len() == 0 {
-                    false
-                } else {
-                    true
+            LiteralsAst::Map(map) => map.borrow().describe(printing),
+            _ => self.to_string()
+        }
+    }
+
+    // `==`, with `comparing` holding the pairs of lists and maps already being
+    // compared further out. Meeting one of those again means the two sides
+    // have the same shape all the way round the cycle, so it counts as equal.
+    pub(crate) fn equals(&self, other: &LiteralsAst, comparing: &mut Vec<(usize, usize)>) -> bool {
+        match (self, other) {
+            (LiteralsAst::Number(a), LiteralsAst::Number(b)) => a == b,
+            (LiteralsAst::Strings(a), LiteralsAst::Strings(b)) => a == b,
+            (LiteralsAst::True, LiteralsAst::True) => true,
+            (LiteralsAst::False, LiteralsAst::False) => true,
+            (LiteralsAst::Null, LiteralsAst::Null) => true,
+            (LiteralsAst::Callable(a), LiteralsAst::Callable(b)) => Rc::ptr_eq(a, b),
+            (LiteralsAst::Native(a), LiteralsAst::Native(b)) => Rc::ptr_eq(a, b),
+            (LiteralsAst::Class(a), LiteralsAst::Class(b)) => Rc::ptr_eq(a, b),
+            (LiteralsAst::Instance(a), LiteralsAst::Instance(b)) => Rc::ptr_eq(a, b),
+            (LiteralsAst::Object(a), LiteralsAst::Object(b)) => a == b,
+            (LiteralsAst::List(a), LiteralsAst::List(b)) => {
+                if Rc::ptr_eq(a, b) {
+                    return true;
                 }
+                let pair = (a.as_ptr() as *const () as usize, b.as_ptr() as *const () as usize);
+                if comparing.contains(&pair) {
+                    return true;
+                }
+                comparing.push(pair);
+                let (a, b) = (a.borrow(), b.borrow());
+                let equal = a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.equals(b, comparing));
+                comparing.pop();
+                return equal;
             },
-            LiteralsAst::True => true,
-            LiteralsAst::False => false,
-            LiteralsAst::Null => false
+            (LiteralsAst::Map(a), LiteralsAst::Map(b)) => Rc::ptr_eq(a, b) || a.borrow().equals(&b.borrow(), comparing),
+            _ => false
         }
     }
 
-    fn is_equal(a: &LiteralsAst, b: &LiteralsAst) -> bool {   
-        println!("is equal function");
+    pub(crate) fn is_equal(a: &LiteralsAst, b: &LiteralsAst) -> bool {   
+        trace!("is equal function");
         if *a == LiteralsAst::Null && *b == LiteralsAst::Null {
-            println!("TRUE");
+            trace!("TRUE");
             return true;
         }
         if *a == LiteralsAst::Null {
-            println!("FALSE");
+            trace!("FALSE");
 
             return false;
         }
-        println!("OPUT");
-        println!("a == b : {:?}", a ==b);
+        trace!("OPUT");
+        trace!("a == b : {:?}", a ==b);
         return a == b;
 
     }
 }
 
-impl Expr {
-    pub fn to_string(&self) -> String {
+// Checks that `index` names an element of a sequence `length` long.
+fn element_index(index: &LiteralsAst, length: usize) -> Result<usize, String> {
+    let position = match index {
+        LiteralsAst::Number(n) if *n >= 0.0 && n.fract() == 0.0 => *n as usize,
+        _ => return Err("Index must be a non-negative integer.".to_string())
+    };
+    if position >= length {
+        return Err(format!("Index {} is out of range for length {}.", position, length));
+    }
+    return Ok(position);
+}
+
+pub fn map_key(value: &LiteralsAst) -> Result<MapKey, String> {
+    match MapKey::from_value(value) {
+        Some(key) => Ok(key),
+        None => Err("Map keys must be strings, numbers, booleans or nil.".to_string())
+    }
+}
+
+// Resolves slice bounds, where a missing bound (nil) means the start or the
+// end of the sequence.
+fn slice_range(start: &LiteralsAst, end: &LiteralsAst, length: usize) -> Result<(usize, usize), String> {
+    let bound = |value: &LiteralsAst, default: usize| -> Result<usize, String> {
+        match value {
+            LiteralsAst::Null => Ok(default),
+            LiteralsAst::Number(n) if *n >= 0.0 && n.fract() == 0.0 && *n as usize <= length => Ok(*n as usize),
+            LiteralsAst::Number(n) => Err(format!("Slice bound {} is out of range for length {}.", n, length)),
+            _ => Err("Slice bounds must be numbers.".to_string())
+        }
+    };
+    let start = bound(start, 0)?;
+    let end = bound(end, length)?;
+    if start > end {
+        return Err(format!("Slice start {} is after its end {}.", start, end));
+    }
+    return Ok((start, end));
+}
+
+// `object[index]`. These three are shared with the VM, so they report a bare
+// message and leave it to the caller to say where it happened.
+pub fn index_value(object: &LiteralsAst, index: &LiteralsAst) -> Result<LiteralsAst, String> {
+    match object {
+        LiteralsAst::List(items) => {
+            let items = items.borrow();
+            let position = element_index(index, items.len())?;
+            return Ok(items[position].clone());
+        },
+        LiteralsAst::Strings(value) => {
+            let position = element_index(index, value.chars().count())?;
+            return Ok(LiteralsAst::Strings(value.chars().nth(position).unwrap_or_default().to_string().into()));
+        },
+        LiteralsAst::Map(map) => {
+            let key = map_key(index)?;
+            let value = map.borrow().get(&key);
+            match value {
+                Some(value) => Ok(value),
+                None => Err(format!("Key '{}' not found in map.", index))
+            }
+        },
+        _ => Err("Only lists, strings and maps can be indexed.".to_string())
+    }
+}
+
+// `object[index] = value`.
+pub fn set_index_value(object: &LiteralsAst, index: &LiteralsAst, value: LiteralsAst) -> Result<LiteralsAst, String> {
+    match object {
+        LiteralsAst::List(items) => {
+            let mut items = items.borrow_mut();
+            let position = element_index(index, items.len())?;
+            items[position] = value.clone();
+            return Ok(value);
+        },
+        LiteralsAst::Map(map) => {
+            let key = map_key(index)?;
+            map.borrow_mut().insert(key, value.clone());
+            return Ok(value);
+        },
+        _ => Err("Only lists and maps support index assignment.".to_string())
+    }
+}
+
+// `object[start:end]`, where either bound may be nil.
+pub fn slice_value(object: &LiteralsAst, start: &LiteralsAst, end: &LiteralsAst) -> Result<LiteralsAst, String> {
+    match object {
+        LiteralsAst::List(items) => {
+            let items = items.borrow();
+            let (start, end) = slice_range(start, end, items.len())?;
+            return Ok(LiteralsAst::List(Rc::new(RefCell::new(items[start..end].to_vec()))));
+        },
+        LiteralsAst::Strings(value) => {
+            let (start, end) = slice_range(start, end, value.chars().count())?;
+            return Ok(LiteralsAst::Strings(value.chars().skip(start).take(end - start).collect::<String>().into()));
+        },
+        _ => Err("Only lists and strings can be sliced.".to_string())
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Expr::Binary { left, operator, right } => {
-                return format!("({} {} {})", operator.lexeme, left.to_string(), right.to_string());
+                write!(f, "({} {} {})", operator.lexeme, left, right)
+            },
+            Expr::Call { callee, paren: _, arguments } => {
+                write!(f, "(call {}", callee)?;
+                for argument in arguments {
+                    write!(f, " {}", argument)?;
+                }
+                write!(f, ")")
+            },
+            Expr::Get { object, name } => {
+                write!(f, "(. {} {})", object, name.lexeme)
             },
             Expr::Grouping { expression } => {
-                return format!("(group {})", expression.to_string());
+                write!(f, "(group {})", expression)
+            },
+            Expr::Index { object, bracket: _, index } => {
+                write!(f, "([] {} {})", object, index)
+            },
+            Expr::List { elements } => {
+                write!(f, "(list")?;
+                for element in elements {
+                    write!(f, " {}", element)?;
+                }
+                write!(f, ")")
+            },
+            Expr::Map { brace: _, entries } => {
+                write!(f, "(map")?;
+                for (key, value) in entries {
+                    write!(f, " {} {}", key, value)?;
+                }
+                write!(f, ")")
             },
             Expr::Literal { value } => {
-                return format!("{}", value.to_string());
+                write!(f, "{}", value)
             },
             Expr::Logical { left, operator, right } => {
-                return format!("({} {} {})", operator.lexeme, left.to_string(), right.to_string());
+                write!(f, "({} {} {})", operator.lexeme, left, right)
+            },
+            Expr::Set { object, name, value } => {
+                write!(f, "(= (. {} {}) {})", object, name.lexeme, value)
+            },
+            Expr::SetIndex { object, bracket: _, index, value } => {
+                write!(f, "(= ([] {} {}) {})", object, index, value)
+            },
+            Expr::Slice { object, bracket: _, start, end } => {
+                write!(f, "(slice {} {} {})", object, start, end)
+            },
+            Expr::Stringify { expression } => {
+                write!(f, "(str {})", expression)
+            },
+            Expr::Super { keyword: _, method, local: _, this: _ } => {
+                write!(f, "(super {})", method.lexeme)
+            },
+            Expr::This { keyword: _, local: _ } => {
+                write!(f, "this")
             },
             Expr::Unary { operator, right } => {
-                return format!("({} {})", operator.lexeme, right.to_string());
+                write!(f, "({} {})", operator.lexeme, right)
             },
-            Expr::Variable { name } => {
-                return format!("{}", name.lexeme);
+            Expr::Variable { name, local: _ } => {
+                write!(f, "{}", name.lexeme)
             },
-            Expr::Assign { name, value } => {
-                return format!("({} {})", name.lexeme, value.to_string());
+            Expr::Assign { name, value, local: _ } => {
+                write!(f, "({} {})", name.lexeme, value)
             },
             Expr::Null => {
-                return "".to_string();
+                write!(f, "")
             }
         }
     }
+}
 
-    pub fn print(&self) {
-        println!("{:?}", self.to_string())
-    }
+impl Expr {
 
-    pub fn evaluate(&self, environment: &mut Environment) -> Result<LiteralsAst, String> {
-        // println!("environment: {:?}\n", environment);
+    pub fn evaluate(&self, interpreter: &mut Interpreter) -> Result<LiteralsAst, RuntimeError> {
+        // println!("environment: {:?}\n", interpreter.environment);
 
         match self {
-            Expr::Assign { name, value } => {
-                println!("Assign in Expr generate_ast");
-                // let value = value.evaluate(environment)?;
-                let val = environment.get(name.lexeme.clone()).unwrap().clone();
-                environment.assign(name.clone(), val.clone());
-                return Ok(val.clone());
+            Expr::Assign { name, value, local } => {
+                trace!("Assign in Expr generate_ast");
+                let value = value.evaluate(interpreter)?;
+                interpreter.assign(*local, name, value.clone())?;
+                return Ok(value);
              },
-            Expr::Variable { name } => {
-                let value = environment.get(name.lexeme.clone());
-                match value {
-                    Some(val) => return Ok(val.clone()),
-                    None => return Err("is not declared".to_string())
+            Expr::Variable { name, local } => {
+                match interpreter.look_up(*local, name) {
+                    Some(val) => return Ok(val),
+                    None => return Err(RuntimeError::new(name.clone(), format!("Undefined variable '{}'.", name.lexeme)))
                 };
                 // Ok(environment.get(&name.lexeme.clone()).unwrap())
              },
@@ -151,8 +381,8 @@ impl Expr {
                 Ok(value.clone())
             },
             Expr::Logical { left, operator, right } => {
-                println!("Expr Logical Reached");
-                let left = left.evaluate(environment)?;
+                trace!("Expr Logical Reached");
+                let left = left.evaluate(interpreter)?;
 
                 if operator.tokentype == TokenType::Or {
                     if left.is_truthy() {
@@ -163,52 +393,157 @@ impl Expr {
                         return Ok(left);
                     }
                   }
-                return right.evaluate(environment);
+                return right.evaluate(interpreter);
+            },
+            Expr::Call { callee, paren, arguments } => {
+                let callee = callee.evaluate(interpreter)?;
+
+                let mut values = Vec::new();
+                for argument in arguments {
+                    values.push(argument.evaluate(interpreter)?);
+                }
+
+                let function: &dyn Callable = match &callee {
+                    LiteralsAst::Callable(function) => function.as_ref(),
+                    LiteralsAst::Native(function) => function.as_ref(),
+                    LiteralsAst::Class(class) => class,
+                    _ => return Err(RuntimeError::new(paren.clone(), "Can only call functions and classes.".to_string()))
+                };
+                if values.len() != function.arity() {
+                    return Err(RuntimeError::new(paren.clone(), format!("Expected {} arguments but got {}.", function.arity(), values.len())));
+                }
+                // Natives, and a call refused for going too deep, don't know
+                // where they were called from, so their errors point at the
+                // call's closing paren.
+                return function.call(interpreter, values).map_err(|error| error.or_at(paren));
+            },
+            Expr::Get { object, name } => {
+                let value = match object.evaluate(interpreter)? {
+                    LiteralsAst::Instance(instance) => LoxInstance::get(&instance, name)?,
+                    _ => return Err(RuntimeError::new(name.clone(), "Only instances have properties.".to_string()))
+                };
+                // A method comes back newly bound to the instance.
+                interpreter.heap.track(&value);
+                return Ok(value);
+            },
+            Expr::Set { object, name, value } => {
+                // Both sides are evaluated before the check, as the VM does.
+                let object = object.evaluate(interpreter)?;
+                let value = value.evaluate(interpreter)?;
+                let instance = match object {
+                    LiteralsAst::Instance(instance) => instance,
+                    _ => return Err(RuntimeError::new(name.clone(), "Only instances have fields.".to_string()))
+                };
+                instance.borrow_mut().set(name, value.clone());
+                return Ok(value);
+            },
+            Expr::Super { keyword, method, local, this } => {
+                if local.is_none() || this.is_none() {
+                    return Err(RuntimeError::new(keyword.clone(), "Can't use 'super' outside of a class.".to_string()));
+                }
+                let superclass = interpreter.look_up(*local, keyword);
+                let object = interpreter.look_up(*this, keyword);
+                let superclass = match superclass {
+                    Some(LiteralsAst::Class(class)) => class,
+                    _ => return Err(RuntimeError::new(keyword.clone(), "Can't use 'super' outside of a class.".to_string()))
+                };
+                match (superclass.find_method(&method.lexeme), object) {
+                    (Some(function), Some(object)) => {
+                        let bound = LiteralsAst::Callable(Rc::new(function.bind(object)));
+                        interpreter.heap.track(&bound);
+                        Ok(bound)
+                    },
+                    _ => Err(RuntimeError::new(method.clone(), format!("Undefined property '{}'.", method.lexeme)))
+                }
+            },
+            Expr::This { keyword, local } => {
+                match interpreter.look_up(*local, keyword) {
+                    Some(val) => Ok(val),
+                    None => Err(RuntimeError::new(keyword.clone(), "Can't use 'this' outside of a class.".to_string()))
+                }
             },
             Expr::Grouping { expression } => {
-               expression.evaluate(environment)
+               expression.evaluate(interpreter)
+            },
+            Expr::List { elements } => {
+                let mut items = Vec::new();
+                for element in elements {
+                    items.push(element.evaluate(interpreter)?);
+                }
+                let list = LiteralsAst::List(Rc::new(RefCell::new(items)));
+                interpreter.heap.track(&list);
+                return Ok(list);
+            },
+            Expr::Map { brace, entries } => {
+                let mut map = LoxMap::new();
+                for (key, value) in entries {
+                    let key = map_key(&key.evaluate(interpreter)?)
+                        .map_err(|message| RuntimeError::new(brace.clone(), message))?;
+                    let value = value.evaluate(interpreter)?;
+                    map.insert(key, value);
+                }
+                let map = LiteralsAst::Map(Rc::new(RefCell::new(map)));
+                interpreter.heap.track(&map);
+                return Ok(map);
+            },
+            Expr::Index { object, bracket, index } => {
+                let object = object.evaluate(interpreter)?;
+                let index = index.evaluate(interpreter)?;
+                return index_value(&object, &index).map_err(|message| RuntimeError::new(bracket.clone(), message));
+            },
+            Expr::SetIndex { object, bracket, index, value } => {
+                let object = object.evaluate(interpreter)?;
+                let index = index.evaluate(interpreter)?;
+                let value = value.evaluate(interpreter)?;
+                return set_index_value(&object, &index, value).map_err(|message| RuntimeError::new(bracket.clone(), message));
+            },
+            Expr::Slice { object, bracket, start, end } => {
+                let object = object.evaluate(interpreter)?;
+                let start = start.evaluate(interpreter)?;
+                let end = end.evaluate(interpreter)?;
+                let slice = slice_value(&object, &start, &end).map_err(|message| RuntimeError::new(bracket.clone(), message))?;
+                interpreter.heap.track(&slice);
+                return Ok(slice);
             },
             Expr::Unary { operator, right } => {
-                let right = right.evaluate(environment)?;
+                let right = right.evaluate(interpreter)?;
                 match (&operator.tokentype, right) {
                     (TokenType::Minus, LiteralsAst::Number(x)) => {
                         return Ok(LiteralsAst::Number(-x));
                     },
                     (TokenType::Minus, _) => {
-                        return Err("Minus Not Implemeted for String".to_string());
+                        return Err(RuntimeError::new(operator.clone(), "Operand must be a number.".to_string()));
                     },
                     (TokenType::Bang, x) => {
                         if x.is_truthy() {
-                            return Ok(LiteralsAst::True);
-                        } else {
                             return Ok(LiteralsAst::False);
+                        } else {
+                            return Ok(LiteralsAst::True);
                         }
                     },
                     (_, _) => {
-                        return Err("Not Implemented".to_string());
+                        return Err(RuntimeError::new(operator.clone(), "Not Implemented".to_string()));
                     }
                 }
             },
+            Expr::Stringify { expression } => {
+                let value = expression.evaluate(interpreter)?;
+                return Ok(LiteralsAst::Strings(value.to_string().into()));
+            },
             Expr::Binary { left, operator, right } => {
-                let right = right.evaluate(environment)?;
-                let left = left.evaluate(environment)?;
+                let left = left.evaluate(interpreter)?;
+                let right = right.evaluate(interpreter)?;
 
                 if operator.tokentype == TokenType::Plus {
                     match (&left, &right) {
                         (LiteralsAst::Number(left), LiteralsAst::Number(right)) => {
-                            return Ok(LiteralsAst::Number((*left) as f64 + (*right) as f64));
+                            return Ok(LiteralsAst::Number(*left + *right));
                         },
                         (LiteralsAst::Strings(left), LiteralsAst::Strings(right)) => {
-                            return Ok(LiteralsAst::Strings(format!("{}{}", left, right))) ;
-                        },
-                        (LiteralsAst::Strings(_), LiteralsAst::Number(_)) => {
-                            return Err("Operands must be two numbers or two strings.".to_string());
-                        },
-                        (LiteralsAst::Number(_), LiteralsAst::Strings(_)) => {
-                            return Err("Operands must be two numbers or two strings.".to_string());
+                            return Ok(LiteralsAst::Strings(format!("{}{}", left, right).into())) ;
                         },
                         (_, _) => {
-                            return Err("Operands must be two numbers or two strings.".to_string());
+                            return Err(RuntimeError::new(operator.clone(), "Operands must be two numbers or two strings.".to_string()));
                         }
                     }
                 }
@@ -218,10 +553,14 @@ impl Expr {
                         return Ok(LiteralsAst::Number((*left)  - (*right))) ;
                     },
                     (LiteralsAst::Number(left),  TokenType::Slash, LiteralsAst::Number(right)) => {
-                        return Ok(LiteralsAst::Number((*left) as f64 / (*right) as f64));
+                        return Ok(LiteralsAst::Number(*left / *right));
                     },
                     (LiteralsAst::Number(left),  TokenType::Star, LiteralsAst::Number(right)) => {
-                        return Ok(LiteralsAst::Number((*left) as f64 * (*right) as f64));
+                        return Ok(LiteralsAst::Number(*left * *right));
+                    },
+                    // Takes the sign of the left operand, like C's fmod.
+                    (LiteralsAst::Number(left),  TokenType::Percent, LiteralsAst::Number(right)) => {
+                        return Ok(LiteralsAst::Number(*left % *right));
                     },
                     (LiteralsAst::Number(left),  TokenType::Greater, LiteralsAst::Number(right)) => {
                         let value = left > right;
@@ -272,7 +611,7 @@ impl Expr {
                         }
                     },
                     (_, _, _) => {
-                        Ok(LiteralsAst::Null)
+                        Err(RuntimeError::new(operator.clone(), "Operands must be numbers.".to_string()))
                     }
                 }
 
@@ -293,13 +632,44 @@ mod tests {
     use crate::scanner::{Scanner};
     use crate::parser::{Parser};
     use crate::interpreter::{Interpreter};
+    use crate::resolver::{Resolver};
+    use crate::symbol::Symbol;
+    use crate::test_support::Captured;
+
+    // Runs a program, returning everything it printed along with the runtime
+    // error that stopped it, if any.
+    fn run(source: &str) -> (String, Result<LiteralsAst, RuntimeError>) {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+
+        let mut parser = Parser::new(tokens);
+        let mut parse = parser.parse().unwrap();
+        Resolver::new().resolve(&mut parse).unwrap();
+
+        let output = Captured::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let result = interpreter.interpret(&parse);
+        return (output.contents(), result);
+    }
+
+    fn output(source: &str) -> String {
+        let (output, result) = run(source);
+        result.unwrap();
+        return output;
+    }
+
+    fn error(source: &str) -> RuntimeError {
+        let (_, result) = run(source);
+        return result.unwrap_err();
+    }
+
     #[test]
     fn ast_print() {
         let expression = Box::new(Expr::Binary { 
             left: Box::new( Expr::Unary { 
-                    operator: Token { tokentype: TokenType::Minus, lexeme: "-".to_string(), literal: Literals::Null, line: 1 }, 
-                    right:Box::new( Expr::Literal { value: LiteralsAst::Number(123 as f64) } )}), 
-            operator: Token { tokentype: TokenType::Star, lexeme: "*".to_string(), literal: Literals::Null, line: 1 }, 
+                    operator: Token { tokentype: TokenType::Minus, lexeme: Symbol::intern("-"), literal: Literals::Null, line: 1, column: 1, offset: 0 }, 
+                    right:Box::new( Expr::Literal { value: LiteralsAst::Number(123.0) } )}), 
+            operator: Token { tokentype: TokenType::Star, lexeme: Symbol::intern("*"), literal: Literals::Null, line: 1, column: 6, offset: 5 }, 
             right: Box::new(Expr::Grouping { expression:Box::new( Expr::Literal { value: LiteralsAst::Number(45.67) }) } )
             }
         );
@@ -310,133 +680,333 @@ mod tests {
 
     #[test]
     fn test_addition() {
-        let source = "2 + 2;".to_string();
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+        assert_eq!(output("print 2 + 2;"), "4\n");
+    }
 
-        let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
+    #[test]
+    fn test_subtraction() {
+        assert_eq!(output("print 42-10;"), "32\n");
+    }
 
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
-        assert_eq!(value, LiteralsAst::Number(4.0));
+    #[test]
+    fn test_multiplication() {
+        assert_eq!(output("print 4 * 10;"), "40\n");
     }
 
     #[test]
-    fn test_subtraction() {
-        let source = "42-10;".to_string();
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+    fn test_division() {
+        assert_eq!(output("print 4 / 2;"), "2\n");
+    }
 
-        let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
-        
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
-        assert_eq!(value, LiteralsAst::Number(32.0));
+    #[test]
+    fn test_concatenation() {
+        assert_eq!(output("print \"Hello\" + \"World\";"), "HelloWorld\n");
     }
 
     #[test]
-    fn test_multiplication() {
-        let source = "4 * 10;".to_string();
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+    fn test_equal_equal() {
+        assert_eq!(output("print 2 == 2;"), "true\n");
+    }
 
-        let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
-        assert_eq!(value, LiteralsAst::Number(40.0));
+    #[test]
+    fn test_bang_equal() {
+        assert_eq!(output("print 2 != 3;"), "true\n");
     }
 
     #[test]
-    fn test_division() {
-        let source = "4 / 2;".to_string();
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+    fn test_greater_than() {
+        assert_eq!(output("print 2 > 3;"), "false\n");
+    }
 
-        let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
-        assert_eq!(value, LiteralsAst::Number(2.0));
+    #[test]
+    fn test_lesser_than() {
+        assert_eq!(output("print 2 < 3;"), "true\n");
     }
 
     #[test]
-    fn test_concatenation() {
-        let source = "\"Hello\" + \"World\";".to_string();
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
-        let mut parser = Parser::new(tokens);
+    fn test_print_formats_values() {
+        let source = "print 2.5; print nil; print \"text\"; fun f() {} print f; class A {} print A; print A();";
+        assert_eq!(output(source), "2.5\nnil\ntext\n<fn f>\nA\nA instance\n");
+    }
 
-        let parse = parser.parse();
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
-        assert_eq!(value, LiteralsAst::Strings("HelloWorld".to_string()));
+    #[test]
+    fn test_statements_run_in_order() {
+        assert_eq!(output("print 1; var a = 2; print a; a = 3; print a;"), "1\n2\n3\n");
     }
 
     #[test]
-    fn test_equal_equal() {
-        let source = "2 == 2".to_string();
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+    fn test_output_before_error_is_kept() {
+        let (output, result) = run("print \"before\"; -\"text\"; print \"after\";");
+        assert_eq!(output, "before\n");
+        assert_eq!(result.unwrap_err().message, "Operand must be a number.");
+    }
 
-        let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
-        assert_eq!(value, LiteralsAst::True);
+    #[test]
+    fn test_report_writes_error_sink() {
+        let errors = Captured::default();
+        let mut interpreter = Interpreter::with_outputs(Box::new(Captured::default()), Box::new(errors.clone()));
+        interpreter.report(&"first");
+        interpreter.report(&"second");
+        assert_eq!(errors.contents(), "first\nsecond\n");
     }
 
     #[test]
-    fn test_bang_equal() {
-        let source = "2 != 3".to_string();
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+    fn test_while_loop() {
+        assert_eq!(output("var i = 0; while (i < 5) i = i + 1; print i;"), "5\n");
+    }
 
-        let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
-        assert_eq!(value, LiteralsAst::True);
+    #[test]
+    fn test_while_loop_false_condition() {
+        assert_eq!(output("var i = 10; while (i < 5) i = i + 1; print i;"), "10\n");
     }
 
     #[test]
-    fn test_greater_than() {
-        let source = "2 > 3".to_string();
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+    fn test_while_loop_block_body() {
+        let source = "var count = 3; while (count) { print count; count = count - 1; }";
+        assert_eq!(output(source), "3\n2\n1\n");
+    }
 
-        let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
-        assert_eq!(value, LiteralsAst::False);
+    #[test]
+    fn test_for_loop() {
+        assert_eq!(output("for (var i = 0; i < 3; i = i + 1) print i;"), "0\n1\n2\n");
     }
 
     #[test]
-    fn test_lesser_than() {
-        let source = "2 < 3".to_string();
+    fn test_for_loop_without_clauses() {
+        assert_eq!(output("var i = 0; for (; i < 3;) i = i + 1; print i;"), "3\n");
+    }
+
+    #[test]
+    fn test_function_call() {
+        assert_eq!(output("fun add(a, b) { return a + b; } print add(1, 2);"), "3\n");
+    }
+
+    #[test]
+    fn test_function_recursion() {
+        let source = "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(10);";
+        assert_eq!(output(source), "55\n");
+    }
+
+    #[test]
+    fn test_function_without_return() {
+        assert_eq!(output("fun noop() { 1 + 1; } print noop();"), "nil\n");
+    }
+
+    #[test]
+    fn test_function_prints() {
+        assert_eq!(output("fun greet(name) { print \"Hi \" + name; } greet(\"a\"); greet(\"b\");"), "Hi a\nHi b\n");
+    }
+
+    #[test]
+    fn test_return_unwinds_loop() {
+        let source = "fun first() { var i = 0; while (true) { if (i == 3) return i; i = i + 1; } } print first();";
+        assert_eq!(output(source), "3\n");
+    }
+
+    #[test]
+    fn test_function_sees_globals() {
+        let source = "var base = 10; fun offset(n) { return base + n; } { var base = 1; print offset(5); }";
+        assert_eq!(output(source), "15\n");
+    }
+
+    #[test]
+    fn test_function_arity() {
+        assert_eq!(error("fun add(a, b) { return a + b; } add(1);").message, "Expected 2 arguments but got 1.");
+    }
+
+    #[test]
+    fn test_call_non_function() {
+        assert_eq!(error("var x = 1; x();").message, "Can only call functions and classes.");
+    }
+
+    #[test]
+    fn test_too_many_arguments() {
+        let source = format!("fun f() {{}} f({});", vec!["1"; 256].join(", "));
         let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+        let tokens = scanner.scan_tokens().unwrap();
 
         let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
-        assert_eq!(value, LiteralsAst::True);
+        let errors = parser.parse().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Can't have more than 255 arguments.");
+    }
+
+    #[test]
+    fn test_closure_counter() {
+        let source = "fun makeCounter() { var i = 0; fun count() { i = i + 1; return i; } return count; } var counter = makeCounter(); print counter(); print counter(); print counter();";
+        assert_eq!(output(source), "1\n2\n3\n");
+    }
+
+    #[test]
+    fn test_closures_are_independent() {
+        let source = "fun makeCounter() { var i = 0; fun count() { i = i + 1; return i; } return count; } var a = makeCounter(); var b = makeCounter(); a(); a(); print b();";
+        assert_eq!(output(source), "1\n");
+    }
+
+    #[test]
+    fn test_closure_reads_enclosing_local() {
+        let source = "fun outer() { var x = \"outer\"; fun inner() { return x; } return inner; } var f = outer(); print f();";
+        assert_eq!(output(source), "outer\n");
+    }
+
+    #[test]
+    fn test_closure_shares_captured_variable() {
+        let source = "var get; var set; { var shared = 1; fun g() { return shared; } fun s(v) { shared = v; } get = g; set = s; } set(42); print get();";
+        assert_eq!(output(source), "42\n");
+    }
+
+    #[test]
+    fn test_closure_binds_at_declaration() {
+        let source = "var a = \"global\"; { fun showA() { print a; } showA(); var a = \"block\"; showA(); }";
+        assert_eq!(output(source), "global\nglobal\n");
+    }
+
+    #[test]
+    fn test_class_fields() {
+        assert_eq!(output("class Point {} var p = Point(); p.x = 3; p.y = 4; print p.x + p.y;"), "7\n");
+    }
+
+    #[test]
+    fn test_class_method_this() {
+        let source = "class Counter { bump() { this.count = this.count + 1; return this.count; } } var c = Counter(); c.count = 10; print c.bump(); print c.bump();";
+        assert_eq!(output(source), "11\n12\n");
+    }
+
+    #[test]
+    fn test_class_initializer() {
+        let source = "class Point { init(x, y) { this.x = x; this.y = y; } sum() { return this.x + this.y; } } print Point(2, 5).sum();";
+        assert_eq!(output(source), "7\n");
+    }
+
+    #[test]
+    fn test_class_initializer_returns_instance() {
+        let source = "class Box { init() { this.value = 1; return; } } var b = Box(); print b.init() == b;";
+        assert_eq!(output(source), "true\n");
+    }
+
+    #[test]
+    fn test_bound_method_keeps_this() {
+        let source = "class Greeter { init(name) { this.name = name; } greet() { return \"Hi \" + this.name; } } var greet = Greeter(\"Lox\").greet; print greet();";
+        assert_eq!(output(source), "Hi Lox\n");
+    }
+
+    #[test]
+    fn test_bound_methods_share_the_declaration() {
+        let (_, result) = run("class A { m() {} } var a = A(); [a.m, a.m];");
+        let LiteralsAst::List(methods) = result.unwrap() else { panic!("Expected a list") };
+        let methods = methods.borrow();
+        let (LiteralsAst::Callable(first), LiteralsAst::Callable(second)) = (&methods[0], &methods[1]) else { panic!("Expected two methods") };
+        assert!(!Rc::ptr_eq(first, second));
+        assert!(Rc::ptr_eq(&first.declaration, &second.declaration));
+    }
+
+    #[test]
+    fn test_field_shadows_method() {
+        let source = "class Thing { name() { return \"method\"; } } var thing = Thing(); thing.name = \"field\"; print thing.name;";
+        assert_eq!(output(source), "field\n");
+    }
+
+    #[test]
+    fn test_instance_display() {
+        assert_eq!(output("class Bagel {} print Bagel();"), "Bagel instance\n");
+    }
+
+    #[test]
+    fn test_class_initializer_arity() {
+        assert_eq!(error("class Point { init(x, y) {} } Point(1);").message, "Expected 2 arguments but got 1.");
+    }
+
+    #[test]
+    fn test_undefined_property() {
+        assert_eq!(error("class Empty {} Empty().missing;").message, "Undefined property 'missing'.");
+    }
+
+    #[test]
+    fn test_property_on_non_instance() {
+        assert_eq!(error("var number = 1; number.field;").message, "Only instances have properties.");
+    }
+
+    #[test]
+    fn test_inherited_method() {
+        assert_eq!(output("class A { name() { return \"A\"; } } class B < A {} print B().name();"), "A\n");
+    }
+
+    #[test]
+    fn test_inherited_through_chain() {
+        let source = "class A { value() { return 1; } } class B < A {} class C < B {} print C().value();";
+        assert_eq!(output(source), "1\n");
+    }
+
+    #[test]
+    fn test_super_call() {
+        let source = "class A { describe() { return \"A\"; } } class B < A { describe() { return super.describe() + \"B\"; } } print B().describe();";
+        assert_eq!(output(source), "AB\n");
+    }
+
+    #[test]
+    fn test_super_binds_this() {
+        let source = "class A { init(x) { this.x = x; } } class B < A { init(x) { super.init(x * 2); } } print B(4).x;";
+        assert_eq!(output(source), "8\n");
+    }
+
+    #[test]
+    fn test_inherit_from_non_class() {
+        assert_eq!(error("var NotAClass = 1; class B < NotAClass {}").message, "Superclass must be a class.");
+    }
+
+    #[test]
+    fn test_super_undefined_method() {
+        let source = "class A {} class B < A { go() { return super.missing(); } } B().go();";
+        assert_eq!(error(source).message, "Undefined property 'missing'.");
+    }
+
+    #[test]
+    fn test_runtime_error_reports_line() {
+        let error = error("var a = 1;\n-\"text\";");
+        assert_eq!(error.token.lexeme, "-");
+        assert_eq!(error.to_string(), "Operand must be a number.\n[line 2]");
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        assert_eq!(error("print missing;").message, "Undefined variable 'missing'.");
+    }
+
+    #[test]
+    fn test_comparison_needs_numbers() {
+        assert_eq!(error("1 < \"two\";").message, "Operands must be numbers.");
+    }
+
+    #[test]
+    fn test_bang_negates() {
+        assert_eq!(output("print !true == false and !nil;"), "true\n");
     }
 
     #[test]
-    #[should_panic(expected = "Operands must be two numbers or two strings.")]
     fn test_addition_fail() {
-        let source = "2 + \"Test\";".to_string();
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+        assert_eq!(error("2 + \"Test\";").message, "Operands must be two numbers or two strings.");
+    }
 
-        let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
-        let mut interpreter = Interpreter::new();
-        let value = interpreter.interpret(parse);
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(output(r#"print "tab\there\nquote \" slash \\";"#), "tab\there\nquote \" slash \\\n");
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let source = r#"var name = "Lox"; var n = 2; print "Hello ${name}! ${n} + ${n} = ${n + n}, ${nil} ${[1, n]}";"#;
+        assert_eq!(output(source), "Hello Lox! 2 + 2 = 4, nil [1, 2]\n");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_nested_interpolation() {
+        assert_eq!(output(r#"var x = 1; print "a ${"b ${x} c"} d"; print "${x}"; print "\${x}";"#), "a b 1 c d\n1\n${x}\n");
+    }
+
+    #[test]
+    fn test_interpolation_evaluates_left_to_right() {
+        let source = r#"fun show(x) { print x; return x; } print "${show(1)} ${show(2)}";"#;
+        assert_eq!(output(source), "1\n2\n1 2\n");
+    }
+}
+
@@ -1,111 +1,403 @@
-use crate::generate_ast::{Expr, LiteralsAst};
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use crate::generate_ast::LiteralsAst;
 use crate::parser::Stmt;
+use crate::callable::LoxFunction;
+use crate::scanner::{Literals, TokenType};
+use crate::stdlib::{self, Globals, NativeState};
+use crate::class::LoxClass;
+use crate::gc::{Heap, Roots};
+use crate::generate_ast::Expr;
+use crate::symbol::{self, Symbol};
+use crate::token::Token;
 use crate::environment;
+use crate::trace::trace;
 
-use environment::Environment;
-use once_cell::sync::Lazy;
-use std::alloc::Layout;
-use std::{env, vec};
-use std::ops::{Deref, DerefMut};
-use std::sync::MutexGuard;
-// use environment::{GLOBAL_ENV};
+use environment::{Captures, Environment, Frame, Local};
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::slice;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+// Deep enough for any sensible recursion, shallow enough to catch a runaway
+// one. Both backends count the top-level code as a frame and stop at this.
+pub const FRAMES_MAX: usize = 4096;
+
+// An error raised while running a program, pointing at the token that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub token: Token,
+    pub message: String
+}
+
+impl RuntimeError {
+    pub fn new(token: Token, message: String) -> Self {
+        Self { token, message }
+    }
+
+    // For code with no token at hand, such as a native function. The call
+    // site swaps in its own token before the error reaches the user.
+    pub fn without_token(message: String) -> Self {
+        return RuntimeError::at_line(0, message);
+    }
+
+    // Points an error raised without a token at `token`; one that already
+    // knows where it came from keeps that.
+    pub fn or_at(self, token: &Token) -> Self {
+        if self.token.tokentype == TokenType::Eof {
+            return RuntimeError::new(token.clone(), self.message);
+        }
+        return self;
+    }
+
+    // For the VM, which only knows the line of the failing instruction.
+    pub fn at_line(line: usize, message: String) -> Self {
+        let token = Token::new(TokenType::Eof, "", Literals::Null, line, 0, 0);
+        Self { token, message }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n[line {}]", self.message, self.token.line)
+    }
+}
 
-#[derive(Debug, Clone)]
+// Anything that stops a run of statements early: a `return` carrying its value
+// back to the call site, or a runtime error.
+#[derive(Debug)]
+pub enum Unwind {
+    Return(LiteralsAst),
+    Error(RuntimeError)
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(err: RuntimeError) -> Self {
+        Unwind::Error(err)
+    }
+}
 
 pub struct Interpreter{
-    environment: Rc<Environment>
+    pub globals: Environment,
+    // The locals of the running function call, or of top-level blocks, and
+    // what the running function captured from the functions around it.
+    frame: Frame,
+    captures: Option<Rc<Captures>>,
+    // How many function calls are running, to stop runaway recursion before
+    // it runs out of Rust stack.
+    call_depth: usize,
+    pub natives: NativeState,
+    pub heap: Heap,
+    // Where `print` writes and where errors are reported; stdout and stderr
+    // unless the embedder hands in its own writers.
+    output: Box<dyn Write>,
+    error_output: Box<dyn Write>
+}
+
+impl fmt::Debug for Interpreter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interpreter")
+            .field("frame", &self.frame)
+            .finish()
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Globals for Interpreter {
+    fn define_global(&mut self, name: &str, value: LiteralsAst) {
+        self.globals.define(Symbol::intern(name), value);
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        println!("New Interpreter");
-        Self {
-            environment: Rc::new(Environment::new())
+        return Interpreter::with_outputs(Box::new(io::stdout()), Box::new(io::stderr()));
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        return Interpreter::with_outputs(output, Box::new(io::stderr()));
+    }
+
+    pub fn with_outputs(output: Box<dyn Write>, error_output: Box<dyn Write>) -> Self {
+        trace!("New Interpreter");
+        let mut interpreter = Self {
+            globals: Environment::new(),
+            frame: Frame::default(),
+            captures: None,
+            call_depth: 0,
+            natives: NativeState::default(),
+            heap: Heap::new(),
+            output: output,
+            error_output: error_output
+        };
+        stdlib::register(&mut interpreter);
+        return interpreter;
+    }
+
+    // Writes a diagnostic to the error sink, one per line.
+    pub fn report(&mut self, error: &dyn fmt::Display) {
+        let _ = writeln!(self.error_output, "{}", error);
+        let _ = self.error_output.flush();
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<LiteralsAst, RuntimeError> {
+        trace!("interpret called");
+        trace!("statements: {:?}\n", statements);
+        // An error can leave a block's locals behind.
+        self.frame = Frame::default();
+        self.captures = None;
+        match self.execute(statements) {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(err)) => Err(err)
         }
     }
 
-    pub fn interpret(&mut self, statements: Vec<Stmt>) -> LiteralsAst {
-        println!("interpret called");
-        println!("statements: {:?}\n", statements);
+    pub fn execute(&mut self, statements: &[Stmt]) -> Result<LiteralsAst, Unwind> {
+        let mut result = LiteralsAst::Null;
         for statement in statements {
-            // println!("STatement: {:?}", statement);
-            match statement {
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+            result = match statement {
                 Stmt::Expression { expression } => {
-                    println!("Stmt Expre");
+                    trace!("Stmt Expre");
 
-                    let value = expression.evaluate( Rc::get_mut(&mut self.environment).expect("Expected a mutable environment")).unwrap();
-                    return value;
+                    expression.evaluate(self)?
                 },
-                Stmt::If { condition, then_branch, else_branch } => {
-                    println!("Stmt If");
+                Stmt::Function { declaration, slot, captures } => {
+                    trace!("Stmt Function");
+                    let name = &declaration.name;
+                    // Bound before it is captured, so the function can call itself.
+                    self.declare(*slot, &name.lexeme, LiteralsAst::Null);
+                    let function = LoxFunction {
+                        declaration: Rc::clone(declaration),
+                        captures: self.capture(captures),
+                        is_initializer: false,
+                        this: None,
+                        superclass: None
+                    };
+                    let function = LiteralsAst::Callable(Rc::new(function));
+                    self.heap.track(&function);
+                    self.initialize(*slot, &name.lexeme, function);
+                    LiteralsAst::Null
+                },
+                Stmt::Class { name, superclass, methods, slot } => {
+                    trace!("Stmt Class");
+                    let superclass = match superclass {
+                        Some(expression) => match expression.evaluate(self)? {
+                            LiteralsAst::Class(class) => Some(class),
+                            _ => {
+                                let token = match expression {
+                                    Expr::Variable { name: superclass_name, local: _ } => superclass_name,
+                                    _ => name
+                                };
+                                return Err(Unwind::Error(RuntimeError::new(token.clone(), "Superclass must be a class.".to_string())));
+                            }
+                        },
+                        None => None
+                    };
+                    self.declare(*slot, &name.lexeme, LiteralsAst::Null);
 
-                    // println!("condition: {:?}", condition);
-                    // println!("then_branch: {:?}", then_branch);
-                    // println!("else_branch: {:?}", else_branch);
+                    let mut class_methods = HashMap::new();
+                    for method in methods {
+                        if let Stmt::Function { declaration, slot: _, captures } = method {
+                            let method_name = &declaration.name;
+                            let function = LoxFunction {
+                                declaration: Rc::clone(declaration),
+                                captures: self.capture(captures),
+                                is_initializer: method_name.lexeme == symbol::init(),
+                                this: None,
+                                superclass: superclass.clone()
+                            };
+                            let function = Rc::new(function);
+                            self.heap.track(&LiteralsAst::Callable(Rc::clone(&function)));
+                            class_methods.insert(method_name.lexeme.clone(), function);
+                        }
+                    }
 
-                    let value = condition.evaluate(Rc::get_mut(&mut self.environment).expect("Expected a mutable environment")).unwrap();
-                    println!("value: {:?}", value);
+                    let class = LoxClass { name: name.lexeme.to_string(), superclass, methods: class_methods };
+                    let class = LiteralsAst::Class(Rc::new(class));
+                    self.heap.track(&class);
+                    self.initialize(*slot, &name.lexeme, class);
+                    LiteralsAst::Null
+                },
+                Stmt::If { condition, then_branch, else_branch } => {
+                    trace!("Stmt If");
+                    let value = condition.evaluate(self)?;
+                    trace!("value: {:?}", value);
 
                     if value.is_truthy() {
-                        self.execute(vec![*then_branch]);
-                    } else if *else_branch.clone().unwrap()  != Stmt::Null {
-                        self.execute(vec![*else_branch.unwrap()]);
+                        self.execute(slice::from_ref(then_branch))?
+                    } else {
+                        match else_branch.as_deref() {
+                            Some(Stmt::Null) | None => LiteralsAst::Null,
+                            Some(else_branch) => self.execute(slice::from_ref(else_branch))?
+                        }
                     }
-                    // return LiteralsAst::Null
                 },
                 Stmt::Print { expression } => {
-                    println!("Stmt Print");
-                    // let env = Rc::make_mut(&mut self.environment);
-                    let value = expression.evaluate(Rc::get_mut(&mut self.environment).expect("Expected a mutable environment"));
-                    let val = match value {
-                        Ok(val) => val,
-                        Err(err) => LiteralsAst::Strings(format!("Variable {} {}", expression.to_string(), err.to_string()))
-                    };
-                    println!("Print ENvironmet: {:?}", Rc::get_mut(&mut self.environment).expect("Expected a mutable environment"));
+                    trace!("Stmt Print");
+                    let val = expression.evaluate(self)?;
+                    trace!("Print frame: {:?}", self.frame);
 
-                    println!("Stmt::Print: {:?}", val);
-                    return val;
+                    trace!("Stmt::Print: {:?}", val);
+                    // A closed pipe or full disk shouldn't abort the script.
+                    let _ = writeln!(self.output, "{}", val);
+                    LiteralsAst::Null
                 },
-                Stmt::Var  { name, initializer } => {
-                    println!("Stmt Var");
-
-                    let value = initializer.evaluate(Rc::get_mut(&mut self.environment).expect("Expected a mutable environment")).unwrap();
-                    let env = Rc::get_mut(&mut self.environment).expect("Expected a mutable environment");
-                    env.define(name.lexeme, value.clone());
-                    // println!(" Stmt::Var: {:?}", value.clone());
-                    return value;
+                Stmt::Return { keyword: _, value } => {
+                    trace!("Stmt Return");
+                    let value = value.evaluate(self)?;
+                    return Err(Unwind::Return(value));
+                },
+                Stmt::Var  { name, initializer, slot } => {
+                    trace!("Stmt Var");
+
+                    let value = initializer.evaluate(self)?;
+                    self.declare(*slot, &name.lexeme, value.clone());
+                    value
                 },
                 Stmt::Block {statements } => {
 
-                    println!("Stmt Block");
-                    let mut new_environment = Environment::new();
-                    new_environment.enclosing = Some(Rc::clone(&self.environment)); 
-                    let previous_environment = std::mem::replace(&mut self.environment, Rc::new(new_environment));
+                    trace!("Stmt Block");
+                    // The block's locals go once it ends.
+                    let start = self.frame.len();
                     let result = self.execute(statements);
-                    
-                    // Restore previous environment
-                    self.environment = previous_environment;
-                    
-                    return result;
+                    self.frame.truncate(start);
+                    result?
+                },
+                Stmt::While { condition, body } => {
+                    trace!("Stmt While");
+                    let mut result = LiteralsAst::Null;
+                    while condition.evaluate(self)?.is_truthy() {
+                        result = self.execute(slice::from_ref(body))?;
+                    }
+                    result
+                },
+                Stmt::ForIn { name, keyword, iterable, body, slot } => {
+                    trace!("Stmt ForIn");
+                    let items = match iterable.evaluate(self)? {
+                        LiteralsAst::List(items) => items,
+                        _ => return Err(Unwind::Error(RuntimeError::new(keyword.clone(), "Can only iterate over a list.".to_string())))
+                    };
+
+                    // The length is read on every pass, so a body that pushes
+                    // to the list also walks the new elements.
+                    let mut result = LiteralsAst::Null;
+                    let mut position = 0;
+                    let start = self.frame.len();
+                    loop {
+                        let item = items.borrow().get(position).cloned();
+                        let Some(item) = item else { break };
+
+                        // A fresh binding each pass, so closures keep their own element.
+                        self.declare(*slot, &name.lexeme, item);
+                        let pass = self.execute(slice::from_ref(body));
+                        self.frame.truncate(start);
+                        result = pass?;
+                        position += 1;
+                    }
+                    result
                 },
                 Stmt::Null => {
-                    println!("Stmt Null");
-                    return LiteralsAst::Null;
+                    trace!("Stmt Null");
+                    LiteralsAst::Null
+                }
+            };
+        }
+        Ok(result)
+    }
+
+    // Runs a function body in its own frame.
+    pub fn execute_call(&mut self, statements: &[Stmt], frame: Frame, captures: Rc<Captures>) -> Result<LiteralsAst, Unwind> {
+        if self.call_depth + 1 >= FRAMES_MAX {
+            return Err(Unwind::Error(RuntimeError::without_token("Stack overflow.".to_string())));
+        }
+        let previous_frame = std::mem::replace(&mut self.frame, frame);
+        let previous_captures = self.captures.replace(captures);
+        self.call_depth += 1;
+        let result = self.execute(statements);
+
+        self.call_depth -= 1;
+        self.frame = previous_frame;
+        self.captures = previous_captures;
+        result
+    }
+
+    // A new binding, in the frame when the resolver gave it a slot and among
+    // the globals otherwise.
+    fn declare(&mut self, slot: Option<usize>, name: &Symbol, value: LiteralsAst) {
+        match slot {
+            Some(slot) => self.frame.define(slot, value),
+            None => self.globals.define(name.clone(), value)
+        }
+    }
+
+    // Sets a binding `declare` just made, reaching closures that have already
+    // captured it.
+    fn initialize(&mut self, slot: Option<usize>, name: &Symbol, value: LiteralsAst) {
+        match slot {
+            Some(slot) => {
+                self.frame.assign(slot, value);
+            },
+            None => self.globals.define(name.clone(), value)
+        }
+    }
+
+    // The cells for a closure created here, from the running call's frame or
+    // passed on from what the running function captured itself.
+    fn capture(&mut self, captures: &[Local]) -> Rc<Captures> {
+        let mut cells = Vec::with_capacity(captures.len());
+        for capture in captures {
+            match capture {
+                Local::Slot(slot) => cells.push(self.frame.capture(*slot)),
+                Local::Captured(index) => {
+                    let cell = self.captures.as_ref().and_then(|captures| captures.cell(*index))
+                        .expect("the resolver only hands on what the running function captured");
+                    cells.push(Rc::clone(cell));
                 }
             }
         }
-        // todo!()
-        LiteralsAst::Null
+        let captures = Captures::new(cells);
+        self.heap.track_captures(&captures);
+        return captures;
     }
 
-    fn execute(&mut self, statement: Vec<Stmt>) -> LiteralsAst {
-        // todo!();
-        // println!("In Execute {:?}", statement);
-        println!("execute ENvironmet: {:?}", Rc::get_mut(&mut self.environment).expect("Expected a mutable environment"));
+    // Nothing here is a root: globals, frames, and values the evaluator is
+    // holding all keep their objects alive through references the collector
+    // counts as coming from outside the heap, which marks them all the same.
+    fn collect_garbage(&mut self) {
+        let collection = self.heap.collect(Roots::default());
+        if self.heap.log {
+            let _ = writeln!(self.error_output, "-- gc: collected {} bytes (from {} to {}) next at {}",
+                collection.freed(), collection.before, collection.after, collection.next_gc);
+        }
+    }
 
-        self.interpret(statement)
+    pub fn look_up(&self, local: Option<Local>, name: &Token) -> Option<LiteralsAst> {
+        match local {
+            None => self.globals.get(&name.lexeme),
+            Some(Local::Slot(slot)) => self.frame.get(slot),
+            Some(Local::Captured(index)) => self.captures.as_ref()?.get(index)
+        }
     }
 
-}
\ No newline at end of file
+    pub fn assign(&mut self, local: Option<Local>, name: &Token, value: LiteralsAst) -> Result<(), RuntimeError> {
+        let assigned = match local {
+            None => return self.globals.assign(name, value),
+            Some(Local::Slot(slot)) => self.frame.assign(slot, value),
+            Some(Local::Captured(index)) => self.captures.as_ref().is_some_and(|captures| captures.assign(index, value))
+        };
+        if !assigned {
+            return Err(RuntimeError::new(name.clone(), format!("Undefined variable '{}'.", name.lexeme)));
+        }
+        return Ok(());
+    }
+
+}
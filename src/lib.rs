@@ -0,0 +1,301 @@
+use std::fmt;
+use std::io::Write;
+
+pub mod scanner;
+pub mod token;
+pub mod generate_ast;
+pub mod parser;
+pub mod interpreter;
+pub mod environment;
+pub mod gc;
+pub mod callable;
+pub mod class;
+pub mod map;
+pub mod resolver;
+pub mod trace;
+pub mod stdlib;
+pub mod symbol;
+pub mod vm;
+
+#[cfg(test)]
+mod test_support;
+
+use scanner::{Scanner, ScanError};
+use parser::{Parser, ParseError};
+use callable::NativeFn;
+use gc::Heap;
+use interpreter::{Interpreter, RuntimeError};
+use resolver::Resolver;
+use stdlib::Globals;
+use symbol::Symbol;
+use trace::trace;
+use vm::Vm;
+use vm::compiler::Compiler;
+
+pub use generate_ast::LiteralsAst;
+pub use stdlib::NativeState;
+
+// Enough stack for the tree-walker to recurse `FRAMES_MAX` calls deep, which
+// takes far more than a thread gets by default, unoptimized builds most of
+// all. Only the pages a script actually touches get committed. The CLI runs
+// scripts on a thread this size; embedders that let scripts recurse deeply
+// should too.
+pub const STACK_SIZE: usize = 1 << 30;
+
+// What a Lox expression evaluates to, under the name embedders expect.
+pub type Value = LiteralsAst;
+
+// Everything that can stop a run, grouped by the stage that raised it so the
+// CLI can pick the matching exit code.
+#[derive(Debug)]
+pub enum LoxError {
+    Scan(Vec<ScanError>),
+    Parse(Vec<ParseError>),
+    Resolve(Vec<String>),
+    Compile(Vec<String>),
+    Runtime(RuntimeError)
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<String> = match self {
+            LoxError::Scan(errors) => errors.iter().map(|error| error.to_string()).collect(),
+            LoxError::Parse(errors) => errors.iter().map(|error| error.to_string()).collect(),
+            LoxError::Resolve(errors) | LoxError::Compile(errors) => errors.clone(),
+            LoxError::Runtime(error) => vec![error.to_string()]
+        };
+        write!(f, "{}", messages.join("\n"))
+    }
+}
+
+impl LoxError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LoxError::Scan(_) | LoxError::Parse(_) | LoxError::Resolve(_) | LoxError::Compile(_) => 65,
+            LoxError::Runtime(_) => 70
+        }
+    }
+}
+
+// Which engine runs the code. Both give the same results; the bytecode VM
+// gets there faster.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    TreeWalker,
+    Vm
+}
+
+#[derive(Debug)]
+enum Engine {
+    TreeWalker(Box<Interpreter>),
+    Vm(Box<Vm>)
+}
+
+// An embeddable Lox session. Globals and declarations persist between calls
+// to `run_source`, the way they do between lines at the REPL.
+#[derive(Debug)]
+pub struct Lox {
+    engine: Engine
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Self { engine: Engine::TreeWalker(Box::default()) }
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Self { engine: Engine::TreeWalker(Box::new(Interpreter::with_output(output))) }
+    }
+
+    pub fn with_outputs(output: Box<dyn Write>, error_output: Box<dyn Write>) -> Self {
+        Self { engine: Engine::TreeWalker(Box::new(Interpreter::with_outputs(output, error_output))) }
+    }
+
+    pub fn with_backend(backend: Backend, output: Box<dyn Write>, error_output: Box<dyn Write>) -> Self {
+        let engine = match backend {
+            Backend::TreeWalker => Engine::TreeWalker(Box::new(Interpreter::with_outputs(output, error_output))),
+            Backend::Vm => Engine::Vm(Box::new(Vm::with_outputs(output, error_output)))
+        };
+        Self { engine }
+    }
+
+    // Scans, parses, resolves and runs `source`, returning the value of its
+    // last statement.
+    pub fn run_source(&mut self, source: &str) -> Result<Value, LoxError> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().map_err(LoxError::Scan)?;
+
+        let mut parser = Parser::new(tokens);
+        let mut parse = parser.parse().map_err(LoxError::Parse)?;
+
+        let mut resolver = Resolver::new();
+        resolver.resolve(&mut parse).map_err(LoxError::Resolve)?;
+
+        let value = match &mut self.engine {
+            Engine::TreeWalker(interpreter) => interpreter.interpret(&parse).map_err(LoxError::Runtime)?,
+            Engine::Vm(vm) => {
+                let function = Compiler::new().compile(&parse).map_err(LoxError::Compile)?;
+                vm.interpret(function).map_err(LoxError::Runtime)?
+            }
+        };
+        trace!("OutputVal: {:?}", value.to_string());
+        Ok(value)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals().define_global(name, value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        match &self.engine {
+            Engine::TreeWalker(interpreter) => interpreter.globals.get(&Symbol::intern(name)),
+            Engine::Vm(vm) => vm.globals.get(&Symbol::intern(name)).cloned()
+        }
+    }
+
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        self.globals().define_native(name, arity, function);
+    }
+
+    // Lists the bytecode of everything run from now on to the error sink.
+    // Only the VM has bytecode to show, so the tree-walker ignores this.
+    pub fn set_disassemble(&mut self, enabled: bool) {
+        if let Engine::Vm(vm) = &mut self.engine {
+            vm.print_code = enabled;
+        }
+    }
+
+    // Collects garbage at every allocation instead of once the heap has grown,
+    // which shakes out objects the engine failed to track or root.
+    pub fn set_gc_stress(&mut self, enabled: bool) {
+        self.heap().stress = enabled;
+    }
+
+    // Reports how much each collection freed on the error sink.
+    pub fn set_gc_log(&mut self, enabled: bool) {
+        self.heap().log = enabled;
+    }
+
+    fn heap(&mut self) -> &mut Heap {
+        match &mut self.engine {
+            Engine::TreeWalker(interpreter) => &mut interpreter.heap,
+            Engine::Vm(vm) => &mut vm.heap
+        }
+    }
+
+    // What the script sees from `args()`.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        match &mut self.engine {
+            Engine::TreeWalker(interpreter) => interpreter.natives.args = args,
+            Engine::Vm(vm) => vm.natives.args = args
+        }
+    }
+
+    // Writes the error to this session's error sink.
+    pub fn report(&mut self, error: &LoxError) {
+        match &mut self.engine {
+            Engine::TreeWalker(interpreter) => interpreter.report(error),
+            Engine::Vm(vm) => vm.report(error)
+        }
+    }
+
+    fn globals(&mut self) -> &mut dyn Globals {
+        match &mut self.engine {
+            Engine::TreeWalker(interpreter) => interpreter.as_mut(),
+            Engine::Vm(vm) => vm.as_mut()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Captured;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_run_source_returns_last_value() {
+        let mut lox = Lox::with_output(Box::new(Captured::default()));
+        assert_eq!(lox.run_source("var a = 2; a * 21;").unwrap(), Value::Number(42.0));
+    }
+
+    #[test]
+    fn test_state_persists_between_runs() {
+        let output = Captured::default();
+        let mut lox = Lox::with_output(Box::new(output.clone()));
+        lox.run_source("fun double(x) { return x * 2; }").unwrap();
+        lox.run_source("print double(4);").unwrap();
+        assert_eq!(output.contents(), "8\n");
+    }
+
+    #[test]
+    fn test_set_and_get_global() {
+        let mut lox = Lox::with_output(Box::new(Captured::default()));
+        lox.set_global("width", Value::Number(3.0));
+        lox.run_source("var area = width * width;").unwrap();
+        assert_eq!(lox.get_global("area"), Some(Value::Number(9.0)));
+        assert_eq!(lox.get_global("missing"), None);
+    }
+
+    fn add(_state: &mut NativeState, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        match (&arguments[0], &arguments[1]) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+            _ => Err(RuntimeError::without_token("add expects two numbers.".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_define_native() {
+        let output = Captured::default();
+        let mut lox = Lox::with_output(Box::new(output.clone()));
+        lox.define_native("add", 2, add);
+        lox.run_source("print add(2, 3); print add;").unwrap();
+        assert_eq!(output.contents(), "5\n<native fn add>\n");
+    }
+
+    #[test]
+    fn test_native_error_points_at_call() {
+        let mut lox = Lox::with_output(Box::new(Captured::default()));
+        lox.define_native("add", 2, add);
+        let error = lox.run_source("var a = 1;\nadd(a, \"b\");").unwrap_err();
+        assert_eq!(error.to_string(), "add expects two numbers.\n[line 2]");
+    }
+
+    #[test]
+    fn test_native_arity_is_checked() {
+        let mut lox = Lox::with_output(Box::new(Captured::default()));
+        lox.define_native("add", 2, add);
+        let error = lox.run_source("add(1);").unwrap_err();
+        assert_eq!(error.to_string(), "Expected 2 arguments but got 1.\n[line 1]");
+    }
+
+    #[test]
+    fn test_equal_literals_share_one_allocation() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut lox = Lox::with_backend(backend, Box::new(Captured::default()), Box::new(Captured::default()));
+            lox.run_source("var a = \"shared\"; fun f() { return \"shared\"; } var b = f();").unwrap();
+            match (lox.get_global("a"), lox.get_global("b")) {
+                (Some(Value::Strings(a)), Some(Value::Strings(b))) => assert!(Rc::ptr_eq(&a, &b)),
+                values => panic!("expected two strings, got {:?}", values)
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors_by_stage() {
+        let mut lox = Lox::with_output(Box::new(Captured::default()));
+        assert!(matches!(lox.run_source("var a = @;"), Err(LoxError::Scan(_))));
+        assert!(matches!(lox.run_source("var = 1;"), Err(LoxError::Parse(_))));
+        assert!(matches!(lox.run_source("return 1;"), Err(LoxError::Resolve(_))));
+
+        let error = lox.run_source("nil();").unwrap_err();
+        assert_eq!(error.exit_code(), 70);
+        assert_eq!(error.to_string(), "Can only call functions and classes.\n[line 1]");
+    }
+}
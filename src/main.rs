@@ -1,70 +1,42 @@
-
 use std::env::args;
 use std::process::exit;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
-use lazy_static::lazy_static;
-use once_cell::sync::Lazy;
-use std::sync::{Arc, Mutex};
-use std::rc::Rc;
-
+use std::thread;
 
-mod scanner;
-mod token;
-mod generate_ast;
-mod parser;
-mod interpreter;
-mod environment;
+use interpreter::{Backend, Lox, STACK_SIZE};
+use interpreter::trace;
 
-use scanner::Scanner;
-use parser::Parser;
-use interpreter::Interpreter;
-use environment::Environment;
-
-fn run_file(path: &str) -> Result<(), String>{
-    let f = fs::read_to_string(path);
-    let mut interpreter = Interpreter::new();
-    let file = match f {
+// A script that can't be read exits with 66, sysexits' EX_NOINPUT, next to
+// the 65 and 70 that compile and runtime errors use.
+fn run_file(path: &str, script_args: Vec<String>, mut lox: Lox) {
+    let file = match fs::read_to_string(path) {
         Ok(file) => file,
-        Err(err) => panic!("Problem opening the file: {err:?}")
+        Err(err) => {
+            eprintln!("Could not read '{}': {}.", path, err);
+            exit(66);
+        }
     };
+    lox.set_args(script_args);
 
-    run(file, &mut interpreter);
-
-    Ok(())
-}
-
-fn run (source: String, interpreter: &mut Interpreter) {
-    let mut scanner = Scanner::new(source);
-    let tokens = scanner.scan_tokens();
-
-    let mut parser = Parser::new(tokens);
-    // println!("parser : {:?}\n", parser);
-
-    // let expr = parser.expression();
-    // println!("Expression: {:?}", expr);
-
-    let parse = parser.parse();
-    // println!("Parse: {:?}\n", parse);
-
-    // let environment = Rc::new(Environment::new());
-    let value = interpreter.interpret(parse);
-    println!("OutputVal: {:?}", value.to_string());
-
+    if let Err(err) = lox.run_source(&file) {
+        lox.report(&err);
+        exit(err.exit_code());
+    }
 }
 
-fn run_prompt() {
+fn run_prompt(mut lox: Lox) {
     let stdin = io::stdin();
     let input = &mut String::new();
-    let mut interpreter = Interpreter::new();
 
-    println!("> ");
     loop {
+        print!("> ");
+        let _ = io::stdout().flush();
         input.clear();
-        let _ = match stdin.lock().read_line(input) {
+        match stdin.lock().read_line(input) {
             Ok(0) => {
-                println!("\nDetected EOF (Ctrl+D). Exiting...");
+                println!();
                 break;
             }
             Ok(value) => {
@@ -74,31 +46,97 @@ fn run_prompt() {
             },
             Err(err) => panic!("Problem reading the input: {err:?}")
         };
-        // println!("input: {:?}", input);
-        run(input.to_string(), &mut interpreter);
+        if let Err(err) = lox.run_source(input) {
+            lox.report(&err);
+        }
     }
+}
 
-    println!("Input in run prompt");
+// The interpreter's own flags, which must come before the script path.
+#[derive(Default)]
+struct Flags {
+    trace: bool,
+    vm: bool,
+    disassemble: bool,
+    gc_stress: bool,
+    gc_log: bool
+}
 
-    let input = input.replace("\n", " ");
-    println!("total input : {}", input);
+// Takes the flags off the front of `args`, stopping at the first argument
+// that isn't one, so the script path and its arguments are left untouched.
+fn take_flags(args: &mut Vec<String>) -> Flags {
+    let mut flags = Flags::default();
+    let mut taken = 0;
+    for arg in args.iter() {
+        match arg.as_str() {
+            "--trace" => flags.trace = true,
+            "--vm" => flags.vm = true,
+            "--disassemble" => flags.disassemble = true,
+            "--gc-stress" => flags.gc_stress = true,
+            "--gc-log" => flags.gc_log = true,
+            _ => break
+        }
+        taken += 1;
+    }
+    args.drain(..taken);
+    flags
 }
 
 fn main() {
-    let args: Vec<String> = args().collect();
-    println!("args: {:?}", args);
-    if args.len() > 2 {
-        println!("Usage: jlox [script]");
-        exit(64);
-    } else if args.len() == 2 {
-        let _ = run_file(&args[0]); // Error aHNDLING
-     } else {
-        run_prompt();
-     }
+    // Every call the tree-walker makes is a few nested Rust calls, so it can
+    // only recurse as deep as the VM on a bigger stack than the main thread's.
+    let session = thread::Builder::new().stack_size(STACK_SIZE).spawn(run_session)
+        .expect("Problem starting the interpreter thread");
+    if session.join().is_err() {
+        // The panic has already been reported; exit the way it would have.
+        exit(101);
+    }
+}
+
+fn run_session() {
+    let mut args: Vec<String> = args().skip(1).collect();
+    let flags = take_flags(&mut args);
+    if flags.trace {
+        trace::enable();
+    }
+    // `--vm` runs the code on the bytecode VM instead of the tree-walker.
+    // `--disassemble` prints the bytecode before running it, so it implies
+    // `--vm`. `--gc-stress` collects on every allocation; `--gc-log` reports
+    // what each collection freed.
+    let mut backend = Backend::TreeWalker;
+    if flags.vm || flags.disassemble {
+        backend = Backend::Vm;
+    }
+    let mut lox = Lox::with_backend(backend, Box::new(io::stdout()), Box::new(io::stderr()));
+    lox.set_disassemble(flags.disassemble);
+    lox.set_gc_stress(flags.gc_stress);
+    lox.set_gc_log(flags.gc_log);
+    if trace::enabled() {
+        eprintln!("args: {:?}", args);
+    }
+    // Anything after the script path is handed to the script through args(),
+    // flags included.
+    if !args.is_empty() {
+        let script_args = args[1..].to_vec();
+        run_file(&args[0], script_args, lox);
+    } else {
+        run_prompt(lox);
+    }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-// {var b=20; print b;}
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
 
-// if (2 == 2) { print "Double IF";}
-// if (2 == 2) { var c =12; print c;}
-// if (2 == 3) { print "Double IF";}
\ No newline at end of file
+    #[test]
+    fn test_flags_stop_at_the_script_path() {
+        let mut args = strings(&["--vm", "--gc-log", "script.lox", "--trace", "x"]);
+        let flags = take_flags(&mut args);
+        assert!(flags.vm && flags.gc_log);
+        assert!(!flags.trace);
+        assert_eq!(args, strings(&["script.lox", "--trace", "x"]));
+    }
+}
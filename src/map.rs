@@ -0,0 +1,205 @@
+#![allow(clippy::needless_return)]
+
+use crate::generate_ast::LiteralsAst;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// The values that can key a map. Numbers are stored by their bits, with -0
+// folded into 0 so keys compare the way `==` does.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Strings(Rc<str>),
+    Number(u64),
+    True,
+    False,
+    Null
+}
+
+impl MapKey {
+    // None for values that can't be keys. NaN is rejected too: it never
+    // equals itself, so it could be stored but never found again.
+    pub fn from_value(value: &LiteralsAst) -> Option<MapKey> {
+        match value {
+            LiteralsAst::Strings(value) => Some(MapKey::Strings(value.clone())),
+            LiteralsAst::Number(n) if n.is_nan() => None,
+            LiteralsAst::Number(n) if *n == 0.0 => Some(MapKey::Number(0f64.to_bits())),
+            LiteralsAst::Number(n) => Some(MapKey::Number(n.to_bits())),
+            LiteralsAst::True => Some(MapKey::True),
+            LiteralsAst::False => Some(MapKey::False),
+            LiteralsAst::Null => Some(MapKey::Null),
+            _ => None
+        }
+    }
+
+    pub fn to_value(&self) -> LiteralsAst {
+        match self {
+            MapKey::Strings(value) => LiteralsAst::Strings(value.clone()),
+            MapKey::Number(bits) => LiteralsAst::Number(f64::from_bits(*bits)),
+            MapKey::True => LiteralsAst::True,
+            MapKey::False => LiteralsAst::False,
+            MapKey::Null => LiteralsAst::Null
+        }
+    }
+}
+
+// A hash map that remembers insertion order, so `keys()` and printing are
+// predictable.
+#[derive(Debug, Clone, Default)]
+pub struct LoxMap {
+    entries: Vec<(MapKey, LiteralsAst)>,
+    positions: HashMap<MapKey, usize>
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<LiteralsAst> {
+        let position = self.positions.get(key)?;
+        return Some(self.entries[*position].1.clone());
+    }
+
+    pub fn contains(&self, key: &MapKey) -> bool {
+        return self.positions.contains_key(key);
+    }
+
+    // Overwriting a key keeps its original position.
+    pub fn insert(&mut self, key: MapKey, value: LiteralsAst) {
+        match self.positions.get(&key) {
+            Some(position) => self.entries[*position].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<LiteralsAst> {
+        let position = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(position);
+        for (_, later) in self.positions.iter_mut() {
+            if *later > position {
+                *later -= 1;
+            }
+        }
+        return Some(value);
+    }
+
+    pub fn keys(&self) -> Vec<LiteralsAst> {
+        return self.entries.iter().map(|(key, _)| key.to_value()).collect();
+    }
+
+    pub fn values(&self) -> Vec<LiteralsAst> {
+        return self.entries.iter().map(|(_, value)| value.clone()).collect();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &LiteralsAst)> {
+        return self.entries.iter().map(|(key, value)| (key, value));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.positions.clear();
+    }
+
+    // See `LiteralsAst::describe`; a map inside itself prints as `{...}`.
+    pub(crate) fn describe(&self, printing: &mut Vec<usize>) -> String {
+        let address = self as *const LoxMap as usize;
+        if printing.contains(&address) {
+            return "{...}".to_string();
+        }
+        printing.push(address);
+        let entries: Vec<String> = self.entries.iter()
+            .map(|(key, value)| format!("{}: {}", key, value.describe(printing)))
+            .collect();
+        printing.pop();
+        return format!("{{{}}}", entries.join(", "));
+    }
+
+    // See `LiteralsAst::equals`.
+    pub(crate) fn equals(&self, other: &LoxMap, comparing: &mut Vec<(usize, usize)>) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        let pair = (self as *const LoxMap as usize, other as *const LoxMap as usize);
+        if comparing.contains(&pair) {
+            return true;
+        }
+        comparing.push(pair);
+        let equal = self.entries.iter()
+            .all(|(key, value)| other.get(key).is_some_and(|other| value.equals(&other, comparing)));
+        comparing.pop();
+        return equal;
+    }
+}
+
+// Two maps are equal when they hold the same entries, whatever their order.
+impl PartialEq for LoxMap {
+    fn eq(&self, other: &Self) -> bool {
+        return self.equals(other, &mut Vec::new());
+    }
+}
+
+// String keys are quoted so `{"1": x}` and `{1: x}` print differently.
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapKey::Strings(value) => write!(f, "\"{}\"", value),
+            _ => write!(f, "{}", self.to_value())
+        }
+    }
+}
+
+impl fmt::Display for LoxMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(&mut Vec::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(value: &str) -> MapKey {
+        return MapKey::Strings(value.into());
+    }
+
+    #[test]
+    fn test_keys_follow_equality() {
+        assert_eq!(MapKey::from_value(&LiteralsAst::Number(-0.0)), MapKey::from_value(&LiteralsAst::Number(0.0)));
+        assert_ne!(MapKey::from_value(&LiteralsAst::Number(1.0)), MapKey::from_value(&LiteralsAst::Strings("1".into())));
+        assert_eq!(MapKey::from_value(&LiteralsAst::Number(f64::NAN)), None);
+    }
+
+    #[test]
+    fn test_display_quotes_string_keys() {
+        let mut map = LoxMap::new();
+        map.insert(key("1"), LiteralsAst::Strings("b".into()));
+        map.insert(MapKey::from_value(&LiteralsAst::Number(1.0)).unwrap(), LiteralsAst::Strings("b".into()));
+        assert_eq!(map.to_string(), "{\"1\": b, 1: b}");
+    }
+
+    #[test]
+    fn test_insertion_order_survives_remove() {
+        let mut map = LoxMap::new();
+        map.insert(key("a"), LiteralsAst::Number(1.0));
+        map.insert(key("b"), LiteralsAst::Number(2.0));
+        map.insert(key("c"), LiteralsAst::Number(3.0));
+        map.insert(key("a"), LiteralsAst::Number(4.0));
+
+        assert_eq!(map.remove(&key("b")), Some(LiteralsAst::Number(2.0)));
+        assert_eq!(map.get(&key("c")), Some(LiteralsAst::Number(3.0)));
+        assert_eq!(map.to_string(), "{\"a\": 4, \"c\": 3}");
+        assert_eq!(map.remove(&key("b")), None);
+    }
+}
@@ -1,26 +1,64 @@
-use std::iter::MapWhile;
-use std::os::unix::process::ExitStatusExt;
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
 
-use crate::scanner::{TokenType};
+use crate::scanner::{Literals, TokenType};
+use crate::trace::trace;
 use crate::token::Token;
 use crate::generate_ast::{Expr, LiteralsAst};
+use crate::environment::Local;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub lexeme: String,
+    pub message: String
+}
 
-#[derive(Debug)]
-struct ParseError;
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lexeme.is_empty() {
+            write!(f, "[line {}] Error at end: {}", self.line, self.message)
+        } else {
+            write!(f, "[line {}] Error at '{}': {}", self.line, self.lexeme, self.message)
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Parser {
     tokens: Vec<Token>,
-    current: usize
+    current: usize,
+    // Errors that don't leave the parser confused, so parsing carries on
+    // without synchronizing.
+    errors: Vec<ParseError>
+}
+
+// A function or method as written. The function values made from it share it
+// instead of copying it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Stmt {
     Expression {expression: Expr},
+    // `slot` on a declaration is where the resolver put it in its function's
+    // frame, or None at global scope. `captures` is what a function's closure
+    // takes from the functions around it, also filled in by the resolver.
+    Function {declaration: Rc<FunctionDecl>, slot: Option<usize>, captures: Vec<Local>},
     If {condition: Expr, then_branch: Box<Stmt>, else_branch: Option<Box<Stmt>>},
     Print {expression: Expr},
-    Var {name: Token, initializer: Expr},
+    Return {keyword: Token, value: Expr},
+    Var {name: Token, initializer: Expr, slot: Option<usize>},
     Block {statements: Vec<Stmt>},
+    Class {name: Token, superclass: Option<Expr>, methods: Vec<Stmt>, slot: Option<usize>},
+    While {condition: Expr, body: Box<Stmt>},
+    ForIn {name: Token, keyword: Token, iterable: Expr, body: Box<Stmt>, slot: Option<usize>},
     Null
 }
 
@@ -28,21 +66,22 @@ impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
        Self {
             tokens: tokens,
-            current: 0
+            current: 0,
+            errors: Vec::new()
        }
     }
 
-    pub fn expression(&mut self) -> Expr {
-        return self.assignment().unwrap();
+    pub fn expression(&mut self) -> Result<Expr, ParseError> {
+        return self.assignment();
     }
 
-    fn equality(&mut self) -> Expr {
+    fn equality(&mut self) -> Result<Expr, ParseError> {
         // println!("equality");
 
-        let mut expression: Expr = self.comparison();
+        let mut expression: Expr = self.comparison()?;
         while self.match_token_type(vec![TokenType::BangEqual, TokenType::EqualEqual]).unwrap() {
             let operator = self.previous();
-            let right: Expr = self.comparison();
+            let right: Expr = self.comparison()?;
 
             expression = *Box::new(Expr::Binary 
                 {   left: Box::new(expression), 
@@ -53,16 +92,16 @@ impl Parser {
 
         };
         // println!("expression: after {:?}", expression);
-        return expression;
+        return Ok(expression);
     }
 
-    fn comparison(&mut self) -> Expr {
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
         // println!("comparison");
 
-        let mut expression: Expr = self.term();
+        let mut expression: Expr = self.term()?;
         while self.match_token_type(vec![TokenType::Greater, TokenType::GreaterEqual, TokenType::Less, TokenType::LessEqual]).unwrap() {
             let operator = self.previous();
-            let right: Expr = self.term();
+            let right: Expr = self.term()?;
 
             expression = *Box::new(Expr::Binary 
                 {   left: Box::new(expression), 
@@ -74,13 +113,13 @@ impl Parser {
         };
         // println!("expression: comparison after {:?}", expression);
 
-        return expression;
+        return Ok(expression);
     }
 
     fn match_token_type(&mut self, tokens: Vec<TokenType>) -> Result<bool, ParseError> {
         for token in tokens {
             if self.check(token) {
-                self.advance();
+                let _ = self.advance();
                 return Ok(true);
             }
         }
@@ -94,6 +133,14 @@ impl Parser {
         return self.peek().tokentype == token_type;
     }
 
+    // Like `check`, but `distance` tokens past the current one.
+    fn check_ahead(&self, distance: usize, token_type: TokenType) -> bool {
+        match self.tokens.get(self.current + distance) {
+            Some(token) => token.tokentype == token_type,
+            None => false
+        }
+    }
+
     fn previous(&self) -> Token {
         return self.tokens[self.current - 1].clone();
     }
@@ -113,12 +160,12 @@ impl Parser {
         return self.tokens[self.current].clone();
     }
 
-    fn term(&mut self) -> Expr {
+    fn term(&mut self) -> Result<Expr, ParseError> {
         // println!("term");
-        let mut expression: Expr = self.factor();
+        let mut expression: Expr = self.factor()?;
         while self.match_token_type(vec![TokenType::Minus, TokenType::Plus]).unwrap() {
             let operator = self.previous();
-            let right: Expr = self.factor();
+            let right: Expr = self.factor()?;
 
             expression = *Box::new(Expr::Binary 
                 {   left: Box::new(expression), 
@@ -130,17 +177,17 @@ impl Parser {
         };
         // println!("expression: term after {:?}", expression);
 
-        return expression;
+        return Ok(expression);
 
     }
 
-    fn factor(&mut self) -> Expr  {
+    fn factor(&mut self) -> Result<Expr, ParseError>  {
         // println!("factor");
 
-        let mut expression = self.unary().unwrap();
-        while self.match_token_type(vec![TokenType::Slash, TokenType::Star]).unwrap() {
+        let mut expression = self.unary()?;
+        while self.match_token_type(vec![TokenType::Slash, TokenType::Star, TokenType::Percent]).unwrap() {
             let operator = self.previous();
-            let right: Expr = self.unary().unwrap();
+            let right: Expr = self.unary()?;
 
             expression = *Box::new(Expr::Binary 
                 {   left: Box::new(expression), 
@@ -150,11 +197,11 @@ impl Parser {
             );
 
         };
-        println!("expression: factor after {:?}", expression);
-        return expression;
+        trace!("expression: factor after {:?}", expression);
+        return Ok(expression);
     }
     
-    fn unary(&mut self) -> Result<Expr, String>  {
+    fn unary(&mut self) -> Result<Expr, ParseError>  {
         // println!("unary");
 
         if self.match_token_type(vec![TokenType::Bang, TokenType::Minus]).unwrap() {
@@ -166,19 +213,76 @@ impl Parser {
                     right:Box::new(right) 
                 } 
             );
-            println!("expression: unary {:?}", expression);
-            println!("OK");
+            trace!("expression: unary {:?}", expression);
+            trace!("OK");
 
             return Ok(expression);
 
         } else {
                 // println!("self.primary");
 
-            return self.primary();
+            return self.call();
+        }
+    }
+
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expression = self.primary()?;
+        loop {
+            if self.match_token_type(vec![TokenType::LeftParen]).unwrap() {
+                expression = self.finish_call(expression)?;
+            } else if self.match_token_type(vec![TokenType::Dot]).unwrap() {
+                let name = self.consume(TokenType::Identifier, "Expect property name after '.'.".to_string())?;
+                expression = Expr::Get { object: Box::new(expression), name };
+            } else if self.match_token_type(vec![TokenType::LeftBracket]).unwrap() {
+                expression = self.finish_index(expression)?;
+            } else {
+                break;
+            }
         }
+        return Ok(expression);
     }
 
-    fn primary(&mut self) -> Result<Expr, String> {
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    let error = self.report_error(&self.peek(), "Can't have more than 255 arguments.");
+                    self.errors.push(error);
+                }
+                arguments.push(self.expression()?);
+                if !self.match_token_type(vec![TokenType::Comma]).unwrap() {
+                    break;
+                }
+            }
+        }
+
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.".to_string())?;
+        return Ok(Expr::Call { callee: Box::new(callee), paren, arguments });
+    }
+
+    // `object[index]`, or the slice `object[start:end]` where either bound may
+    // be left out.
+    fn finish_index(&mut self, object: Expr) -> Result<Expr, ParseError> {
+        let mut start = Expr::Null;
+        if !self.check(TokenType::Colon) {
+            start = self.expression()?;
+        }
+
+        if self.match_token_type(vec![TokenType::Colon]).unwrap() {
+            let mut end = Expr::Null;
+            if !self.check(TokenType::RightBracket) {
+                end = self.expression()?;
+            }
+            let bracket = self.consume(TokenType::RightBracket, "Expect ']' after slice.".to_string())?;
+            return Ok(Expr::Slice { object: Box::new(object), bracket, start: Box::new(start), end: Box::new(end) });
+        }
+
+        let bracket = self.consume(TokenType::RightBracket, "Expect ']' after index.".to_string())?;
+        return Ok(Expr::Index { object: Box::new(object), bracket, index: Box::new(start) });
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
         // println!("primary");
         // println!("primary self: {:?}", self);
         if self.match_token_type(vec![TokenType::False]).unwrap() {
@@ -194,7 +298,11 @@ impl Parser {
         }
 
         if self.match_token_type(vec![TokenType::String]).unwrap() {
-            return Ok(Expr::Literal { value: LiteralsAst::Strings(self.previous().literal.to_string()) });
+            return Ok(string_literal(&self.previous()));
+        }
+
+        if self.match_token_type(vec![TokenType::Interpolation]).unwrap() {
+            return self.interpolation();
         }
 
         if self.match_token_type(vec![TokenType::Number]).unwrap() {
@@ -202,44 +310,120 @@ impl Parser {
             return Ok(Expr::Literal { value: LiteralsAst::Number(output) });
         }
 
+        if self.match_token_type(vec![TokenType::Super]).unwrap() {
+            let keyword = self.previous();
+            let _ = self.consume(TokenType::Dot, "Expect '.' after 'super'.".to_string())?;
+            let method = self.consume(TokenType::Identifier, "Expect superclass method name.".to_string())?;
+            return Ok(Expr::Super { keyword, method, local: None, this: None });
+        }
+
+        if self.match_token_type(vec![TokenType::This]).unwrap() {
+            return Ok(Expr::This { keyword: self.previous(), local: None });
+        }
+
         if self.match_token_type(vec![TokenType::Identifier]).unwrap() {
-            return Ok(Expr::Variable{ name: self.previous()});
+            return Ok(Expr::Variable{ name: self.previous(), local: None });
+        }
+
+        if self.match_token_type(vec![TokenType::LeftBracket]).unwrap() {
+            let mut elements = Vec::new();
+            if !self.check(TokenType::RightBracket) {
+                loop {
+                    elements.push(self.expression()?);
+                    if !self.match_token_type(vec![TokenType::Comma]).unwrap() {
+                        break;
+                    }
+                }
+            }
+            let _ = self.consume(TokenType::RightBracket, "Expect ']' after list elements.".to_string())?;
+            return Ok(Expr::List { elements });
+        }
+
+        // In expression position `{` can only start a map literal.
+        if self.match_token_type(vec![TokenType::LeftBrace]).unwrap() {
+            let brace = self.previous();
+            let mut entries = Vec::new();
+            if !self.check(TokenType::RightBrace) {
+                loop {
+                    let key = self.expression()?;
+                    let _ = self.consume(TokenType::Colon, "Expect ':' after map key.".to_string())?;
+                    let value = self.expression()?;
+                    entries.push((key, value));
+                    if !self.match_token_type(vec![TokenType::Comma]).unwrap() {
+                        break;
+                    }
+                }
+            }
+            let _ = self.consume(TokenType::RightBrace, "Expect '}' after map entries.".to_string())?;
+            return Ok(Expr::Map { brace, entries });
         }
 
         if self.match_token_type(vec![TokenType::LeftParen]).unwrap() {
-            let _ = self.advance().unwrap();
-            let expression = self.expression();
-            let _ = self.consume(TokenType::RightParen, "Expect ')' after expression.".to_string());
+            let expression = self.expression()?;
+            let _ = self.consume(TokenType::RightParen, "Expect ')' after expression.".to_string())?;
             return Ok(Expr::Grouping { expression: Box::new(expression) });
         }
 
 
         else {
-            print!("Match Error in prumary");
-            return Err(self.report_error(&self.peek(), &"Expect expression.".to_string()));
+            trace!("Match Error in prumary");
+            return Err(self.report_error(&self.peek(), "Expect expression."));
+        }
+    }
+
+    // `"a ${x} b"` arrives as Interpolation("a "), the tokens of `x`, then
+    // String(" b"), and becomes ("a " + str(x)) + " b".
+    fn interpolation(&mut self) -> Result<Expr, ParseError> {
+        let start = self.previous();
+        let plus = Token::new(TokenType::Plus, "+", Literals::Null, start.line, start.column, start.offset);
+        let mut expression = string_literal(&start);
+
+        loop {
+            let inner = self.expression()?;
+            expression = Expr::Binary {
+                left: Box::new(expression),
+                operator: plus.clone(),
+                right: Box::new(Expr::Stringify { expression: Box::new(inner) })
+            };
+
+            let part = if self.match_token_type(vec![TokenType::Interpolation]).unwrap() {
+                self.previous()
+            } else {
+                self.consume(TokenType::String, "Expect '}' after interpolated expression.".to_string())?
+            };
+            expression = Expr::Binary {
+                left: Box::new(expression),
+                operator: plus.clone(),
+                right: Box::new(string_literal(&part))
+            };
+            if part.tokentype == TokenType::String {
+                return Ok(expression);
+            }
         }
     }
 
-    fn consume(&mut self, token_type: TokenType, message: String) -> Result<Token, String> {
+    fn consume(&mut self, token_type: TokenType, message: String) -> Result<Token, ParseError> {
         if self.check(token_type) {
-            return Ok(self.advance().unwrap());
+            return self.advance();
         }
         return Err(self.report_error(&self.peek(), &message));
-        // Err(ParseError)
     }
 
-    fn report_error(&self, token: &Token, message: &str) -> String {
-        if token.tokentype == TokenType::Eof {
-            return format!("[line {}] Error at end: {}", token.line, message);
+    fn report_error(&self, token: &Token, message: &str) -> ParseError {
+        let lexeme = if token.tokentype == TokenType::Eof {
+            "".to_string()
         } else {
-            return format!("[line {}] Error at '{}': {}", token.line, token.lexeme, message);
-        }
+            token.lexeme.to_string()
+        };
+        return ParseError { line: token.line, column: token.column, lexeme, message: message.to_string() };
     }
 
+    // Skips tokens until the start of the next statement so one mistake doesn't
+    // cascade into a pile of bogus errors.
     fn synchronize(&mut self) {
         let _ = self.advance();
 
-        while self.is_at_end() {
+        while !self.is_at_end() {
             if self.previous().tokentype == TokenType::Semicolon {
                 return;
             }
@@ -249,48 +433,95 @@ impl Parser {
                 TokenType::Print | TokenType::Return => return,
                 _ => ()
             }
+            let _ = self.advance();
         }
-        let _ = self.advance();
     }
 
-    pub fn parse(&mut self) -> Vec<Stmt> {
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration().unwrap());
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
-        return statements;
+
+        if self.errors.is_empty() {
+            return Ok(statements);
+        }
+        return Err(std::mem::take(&mut self.errors));
     }
 
     fn statement(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token_type(vec![TokenType::For]).unwrap() {
+            return self.for_statement();
+        }
         if self.match_token_type(vec![TokenType::If]).unwrap() {
-            return Ok(self.if_statement());
+            return self.if_statement();
         }
         if self.match_token_type(vec![TokenType::Print]).unwrap() {
-            println!("Token in statemnt parser.rs");
-            return Ok(self.print_statement());
+            trace!("Token in statemnt parser.rs");
+            return self.print_statement();
         }
-        if self.match_token_type(vec![TokenType::LeftBrace]).unwrap() {
-            return Ok(Stmt::Block { statements: self.block() });
+        if self.match_token_type(vec![TokenType::Return]).unwrap() {
+            return self.return_statement();
+        }
+        if self.match_token_type(vec![TokenType::While]).unwrap() {
+            return self.while_statement();
+        }
+        if self.check(TokenType::LeftBrace) && !self.map_ahead() {
+            let _ = self.advance();
+            return Ok(Stmt::Block { statements: self.block()? });
         }
-        return Ok(self.expression_statement());
+        return self.expression_statement();
     }
 
-    fn print_statement(&mut self) -> Stmt {
-        let value: Expr = self.expression();
-        println!("VALUE: {:?}", value);
-        let _ = self.consume(TokenType::Semicolon, "Expect ';' after value.".to_string());
+    // Whether the `{` at the current token opens a map literal rather than a
+    // block, which it does when an expression and a `:` follow it. No
+    // statement starts that way, and the key can be any expression, so it
+    // takes a trial parse rather than a fixed lookahead. Whatever the trial
+    // parse did is undone.
+    fn map_ahead(&mut self) -> bool {
+        let (start, errors) = (self.current, self.errors.len());
+        let _ = self.advance();
+        let is_map = self.expression().is_ok() && self.check(TokenType::Colon);
+        self.current = start;
+        self.errors.truncate(errors);
+        return is_map;
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let value: Expr = self.expression()?;
+        trace!("VALUE: {:?}", value);
+        let _ = self.consume(TokenType::Semicolon, "Expect ';' after value.".to_string())?;
         // println!("Token in print_statement parser.rs");
-        return Stmt::Print { expression: value };
+        return Ok(Stmt::Print { expression: value });
     }
 
-    fn expression_statement(&mut self) -> Stmt {
-        let expr: Expr = self.expression();
-        let _ = self.consume(TokenType::Semicolon, "Expect ';' after expression.".to_string());
-        return Stmt::Expression { expression: expr };
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        let mut value = Expr::Null;
+        if !self.check(TokenType::Semicolon) {
+            value = self.expression()?;
+        }
+
+        let _ = self.consume(TokenType::Semicolon, "Expect ';' after return value.".to_string())?;
+        return Ok(Stmt::Return { keyword, value });
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
+        let expr: Expr = self.expression()?;
+        let _ = self.consume(TokenType::Semicolon, "Expect ';' after expression.".to_string())?;
+        return Ok(Stmt::Expression { expression: expr });
     }
 
     fn declaration(&mut self) -> Option<Stmt> {
         let result = (|| {
+            if self.match_token_type(vec![TokenType::Class]).unwrap() {
+                return self.class_declaration();
+            }
+            if self.match_token_type(vec![TokenType::Fun]).unwrap() {
+                return self.function("function");
+            }
             if self.match_token_type(vec![TokenType::Var]).unwrap() {
                 return self.var_declaration();
             }
@@ -299,98 +530,232 @@ impl Parser {
     
         match result {
             Ok(stmt) => Some(stmt),
-            Err(_) => {
+            Err(error) => {
+                self.errors.push(error);
                 self.synchronize();
                 None
             }
         }
     }
 
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, "Expect class name.".to_string())?;
+
+        let mut superclass = None;
+        if self.match_token_type(vec![TokenType::Less]).unwrap() {
+            let _ = self.consume(TokenType::Identifier, "Expect superclass name.".to_string())?;
+            superclass = Some(Expr::Variable { name: self.previous(), local: None });
+        }
+
+        let _ = self.consume(TokenType::LeftBrace, "Expect '{' before class body.".to_string())?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+
+        let _ = self.consume(TokenType::RightBrace, "Expect '}' after class body.".to_string())?;
+        return Ok(Stmt::Class { name, superclass, methods, slot: None });
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, format!("Expect {} name.", kind))?;
+        let _ = self.consume(TokenType::LeftParen, format!("Expect '(' after {} name.", kind))?;
+
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    let error = self.report_error(&self.peek(), "Can't have more than 255 parameters.");
+                    self.errors.push(error);
+                }
+                params.push(self.consume(TokenType::Identifier, "Expect parameter name.".to_string())?);
+                if !self.match_token_type(vec![TokenType::Comma]).unwrap() {
+                    break;
+                }
+            }
+        }
+        let _ = self.consume(TokenType::RightParen, "Expect ')' after parameters.".to_string())?;
+
+        let _ = self.consume(TokenType::LeftBrace, format!("Expect '{{' before {} body.", kind))?;
+        let body = self.block()?;
+        let declaration = Rc::new(FunctionDecl { name, params, body });
+        return Ok(Stmt::Function { declaration, slot: None, captures: Vec::new() });
+    }
+
     fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
-        let name = self.consume(TokenType::Identifier, "Expect variable name.".to_string()).unwrap();
+        let name = self.consume(TokenType::Identifier, "Expect variable name.".to_string())?;
         let mut initializer= Expr::Null;
         if self.match_token_type(vec![TokenType::Equal]).unwrap() {
-            initializer = self.expression();
+            initializer = self.expression()?;
         }
 
-        let _ = self.consume(TokenType::Semicolon, "Expect ';' after variable declaration.".to_string());   
-        return  Ok(Stmt::Var { name, initializer});
+        let _ = self.consume(TokenType::Semicolon, "Expect ';' after variable declaration.".to_string())?;
+        return  Ok(Stmt::Var { name, initializer, slot: None });
     }
 
-    pub fn assignment(&mut self) -> Result<Expr, String>{
-        println!("assignmnt reached");
-        let expr = self.or();
+    pub fn assignment(&mut self) -> Result<Expr, ParseError>{
+        trace!("assignmnt reached");
+        let expr = self.or()?;
         // let expr = self.equality();
 
         if self.match_token_type(vec![TokenType::Equal]).unwrap(){
             let equal = self.previous();
             let value = self.assignment()?;
 
-            let name = match expr {
-                Expr::Variable { name } => {
-                    return Ok(Expr::Assign { name: name, value: Box::new(value) });
+            match expr {
+                Expr::Variable { name, local: _ } => {
+                    return Ok(Expr::Assign { name: name, value: Box::new(value), local: None });
+                },
+                Expr::Get { object, name } => {
+                    return Ok(Expr::Set { object, name, value: Box::new(value) });
+                },
+                Expr::Index { object, bracket, index } => {
+                    return Ok(Expr::SetIndex { object, bracket, index, value: Box::new(value) });
                 },
                 _ => {
-                    return Err(format!("{:?} Invalid assignment target.", equal));
+                    // The parser isn't confused here, so report without unwinding.
+                    let error = self.report_error(&equal, "Invalid assignment target.");
+                    self.errors.push(error);
+                    return Ok(expr);
                 }
-                
             };
         }
         return Ok(expr);
     }
 
-    fn block(&mut self) -> Vec<Stmt> {
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut statements  = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration().unwrap());
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
 
-        let _ = self.consume(TokenType::RightBrace, "Expect '}' after block.".to_string());
-        return statements;
+        let _ = self.consume(TokenType::RightBrace, "Expect '}' after block.".to_string())?;
+        return Ok(statements);
     }
 
-    fn if_statement(&mut self)-> Stmt {
-        println!("if_statement");
-        let _ = self.consume(TokenType::LeftParen, "Expect '(' after 'if'.".to_string()).unwrap();
-        let condition = self.expression();
+    fn if_statement(&mut self)-> Result<Stmt, ParseError> {
+        trace!("if_statement");
+        let _ = self.consume(TokenType::LeftParen, "Expect '(' after 'if'.".to_string())?;
+        let condition = self.expression()?;
         // println!("CONDITION: {:?}", condition);
-        let _ = self.consume(TokenType::RightParen, "Expect ')' after 'if' condition.".to_string()).unwrap();
+        let _ = self.consume(TokenType::RightParen, "Expect ')' after 'if' condition.".to_string())?;
 
-        let then_branch = self.statement().unwrap();
+        let then_branch = self.statement()?;
         let mut else_branch = Stmt::Null;
 
         if self.match_token_type(vec![TokenType::Else]).unwrap() {
-            else_branch = self.statement().unwrap();
+            else_branch = self.statement()?;
         };
 
-        return Stmt::If { condition, then_branch: Box::new(then_branch), else_branch: Some(Box::new(else_branch)) };
+        return Ok(Stmt::If { condition, then_branch: Box::new(then_branch), else_branch: Some(Box::new(else_branch)) });
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let _ = self.consume(TokenType::LeftParen, "Expect '(' after 'while'.".to_string())?;
+        let condition = self.expression()?;
+        let _ = self.consume(TokenType::RightParen, "Expect ')' after condition.".to_string())?;
+        let body = self.statement()?;
+
+        return Ok(Stmt::While { condition, body: Box::new(body) });
     }
 
-    fn or(&mut self)-> Expr {
-        println!("Or in parser ");
-        let mut expr = self.and();
+    // A `for` loop is sugar over `while`: the initializer and the loop live in
+    // their own block, and the increment runs after the body on every pass.
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let _ = self.consume(TokenType::LeftParen, "Expect '(' after 'for'.".to_string())?;
+
+        if self.check(TokenType::Var) && self.check_ahead(2, TokenType::In) {
+            return self.for_in_statement();
+        }
+
+        let initializer;
+        if self.match_token_type(vec![TokenType::Semicolon]).unwrap() {
+            initializer = Stmt::Null;
+        } else if self.match_token_type(vec![TokenType::Var]).unwrap() {
+            initializer = self.var_declaration()?;
+        } else {
+            initializer = self.expression_statement()?;
+        }
+
+        let mut condition = Expr::Null;
+        if !self.check(TokenType::Semicolon) {
+            condition = self.expression()?;
+        }
+        let _ = self.consume(TokenType::Semicolon, "Expect ';' after loop condition.".to_string())?;
+
+        let mut increment = Expr::Null;
+        if !self.check(TokenType::RightParen) {
+            increment = self.expression()?;
+        }
+        let _ = self.consume(TokenType::RightParen, "Expect ')' after for clauses.".to_string())?;
+
+        let mut body = self.statement()?;
+
+        if increment != Expr::Null {
+            body = Stmt::Block { statements: vec![body, Stmt::Expression { expression: increment }] };
+        }
+        if condition == Expr::Null {
+            condition = Expr::Literal { value: LiteralsAst::True };
+        }
+        body = Stmt::While { condition, body: Box::new(body) };
+
+        if initializer != Stmt::Null {
+            body = Stmt::Block { statements: vec![initializer, body] };
+        }
+        return Ok(body);
+    }
+
+    // `for (var item in list) body`, which runs the body once per element with
+    // `item` bound in a fresh scope each time.
+    fn for_in_statement(&mut self) -> Result<Stmt, ParseError> {
+        let _ = self.consume(TokenType::Var, "Expect 'var' in for-in loop.".to_string())?;
+        let name = self.consume(TokenType::Identifier, "Expect variable name.".to_string())?;
+        let keyword = self.consume(TokenType::In, "Expect 'in' after loop variable.".to_string())?;
+        let iterable = self.expression()?;
+        let _ = self.consume(TokenType::RightParen, "Expect ')' after for-in clause.".to_string())?;
+        let body = self.statement()?;
+
+        return Ok(Stmt::ForIn { name, keyword, iterable, body: Box::new(body), slot: None });
+    }
+
+    fn or(&mut self)-> Result<Expr, ParseError> {
+        trace!("Or in parser ");
+        let mut expr = self.and()?;
         while self.match_token_type(vec![TokenType::Or]).unwrap() {
             let operator = self.previous();
-            let right = self.and();
+            let right = self.and()?;
             expr = Expr::Logical { left: Box::new(expr), operator: operator, right: Box::new(right) }
         }
-        println!("or in parser expr {:?}", expr);
-        expr
+        trace!("or in parser expr {:?}", expr);
+        Ok(expr)
     } 
 
-    fn and(&mut self)-> Expr {
-        let mut expr = self.equality();
+    fn and(&mut self)-> Result<Expr, ParseError> {
+        let mut expr = self.equality()?;
         while self.match_token_type(vec![TokenType::And]).unwrap() {
             let operator = self.previous();
-            let right = self.and();
+            let right = self.and()?;
             expr = Expr::Logical { left: Box::new(expr), operator: operator, right: Box::new(right) }
         }
-        expr
+        Ok(expr)
     } 
 
 
 }
 
+// A string or interpolation token's text, shared with the scanner's symbol so
+// equal literals are a single allocation.
+fn string_literal(token: &Token) -> Expr {
+    let text = match &token.literal {
+        Literals::String(text) => text.text(),
+        literal => literal.to_string().into()
+    };
+    return Expr::Literal { value: LiteralsAst::Strings(text) };
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -400,32 +765,145 @@ mod tests {
     fn test_comp() {
         let source = "1 + 5 == 2 + 2;".to_string();
         let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+        let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
-        let parse= parser.parse();
+        let parse= parser.parse().unwrap();
 
-        // assert_eq!(parse.to_string(), "(== (+ 1 5) (+ 2 2))");
+        match &parse[0] {
+            Stmt::Expression { expression } => assert_eq!(expression.to_string(), "(== (+ 1 5) (+ 2 2))"),
+            statement => panic!("Expected an expression statement, got {:?}", statement)
+        }
     }
 
     #[test]
     fn test_comp_paren() {
         let source = "(2 + 4 +6) != (5 +7 * 2);".to_string();
         let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+        let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
-        let parse= parser.parse();
+        let parse= parser.parse().unwrap();
 
-        // assert_eq!(parse.to_string(), "(!= (group (+ (+ 2 4) 6)) (group (+ 5 (* 7 2))))");
+        match &parse[0] {
+            Stmt::Expression { expression } => assert_eq!(expression.to_string(), "(!= (group (+ (+ 2 4) 6)) (group (+ 5 (* 7 2))))"),
+            statement => panic!("Expected an expression statement, got {:?}", statement)
+        }
     }
 
     #[test]
     fn test_comp_paren_2() {
         let source = "1 == (2);".to_string();
         let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let parse = parser.parse().unwrap();
+
+        match &parse[0] {
+            Stmt::Expression { expression } => assert_eq!(expression.to_string(), "(== 1 (group 2))"),
+            statement => panic!("Expected an expression statement, got {:?}", statement)
+        }
+    }
+
+    #[test]
+    fn test_reports_every_parse_error() {
+        let source = "var = 1;\nprint 2;\nprint (3;\nprint 4;".to_string();
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let errors = parser.parse().unwrap_err();
+
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(messages, vec![
+            "[line 1] Error at '=': Expect variable name.",
+            "[line 3] Error at ';': Expect ')' after expression.",
+        ]);
+    }
+
+    #[test]
+    fn test_error_at_end() {
+        let source = "print 1".to_string();
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let errors = parser.parse().unwrap_err();
+
+        assert_eq!(errors, vec![ParseError { line: 1, column: 8, lexeme: "".to_string(), message: "Expect ';' after value.".to_string() }]);
+        assert_eq!(errors[0].to_string(), "[line 1] Error at end: Expect ';' after value.");
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let source = "1 = 2;\nvar a;\na + 1 = 3;".to_string();
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let errors = parser.parse().unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "[line 1] Error at '=': Invalid assignment target.");
+        assert_eq!(errors[1].line, 3);
+    }
+
+    #[test]
+    fn test_recovers_inside_block() {
+        let source = "{ var 1; print 2; }\nfun f( { }\nprint 3;".to_string();
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let errors = parser.parse().unwrap_err();
+
+        assert_eq!(errors[0].to_string(), "[line 1] Error at '1': Expect variable name.");
+        assert_eq!(errors[1].to_string(), "[line 2] Error at '{': Expect parameter name.");
+    }
+
+    #[test]
+    fn test_index_and_slice() {
+        let source = "xs[1] = [a, 2][0:];\nxs[:n];".to_string();
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
-        let parse = parser.parse();
+        let parse = parser.parse().unwrap();
 
-        // assert_eq!(parse.to_string(), "(== 1 (group 2))");
+        let printed: Vec<String> = parse.iter().map(|statement| match statement {
+            Stmt::Expression { expression } => expression.to_string(),
+            statement => panic!("Expected an expression statement, got {:?}", statement)
+        }).collect();
+        assert_eq!(printed, vec!["(= ([] xs 1) (slice (list a 2) 0 ))", "(slice xs  n)"]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_slice_is_not_assignable() {
+        let source = "xs[0:1] = 2;".to_string();
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let errors = parser.parse().unwrap_err();
+
+        assert_eq!(errors[0].to_string(), "[line 1] Error at '=': Invalid assignment target.");
+    }
+
+    #[test]
+    fn test_interpolation_desugars_to_concatenation() {
+        let source = "\"a ${x} b ${y}\";".to_string();
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let parse = parser.parse().unwrap();
+
+        match &parse[0] {
+            Stmt::Expression { expression } => assert_eq!(expression.to_string(), "(+ (+ (+ (+ a  (str x))  b ) (str y)) )"),
+            statement => panic!("Expected an expression statement, got {:?}", statement)
+        }
+    }
+
+    #[test]
+    fn test_unclosed_interpolated_expression() {
+        let source = "\"a ${x y}\";".to_string();
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let errors = parser.parse().unwrap_err();
+
+        assert_eq!(errors[0].to_string(), "[line 1] Error at 'y': Expect '}' after interpolated expression.");
+    }
+}
+
@@ -0,0 +1,507 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::environment::Local;
+use crate::generate_ast::Expr;
+use crate::parser::{FunctionDecl, Stmt};
+use crate::scanner::TokenType;
+use crate::symbol::{self, Symbol};
+use crate::token::Token;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass
+}
+
+// A declared local. `defined` is set once its initializer has been resolved.
+#[derive(Debug, Clone, Copy)]
+struct Variable {
+    slot: usize,
+    defined: bool
+}
+
+// The block scopes open in one function body, and how many frame slots their
+// locals take up. A scope's slots are handed out again once it closes.
+// `captures` lists the locals of enclosing functions the body uses, each as
+// the function around it sees them.
+#[derive(Debug, Default)]
+struct FunctionScopes {
+    scopes: Vec<HashMap<Symbol, Variable>>,
+    slots: usize,
+    captures: Vec<Local>
+}
+
+impl FunctionScopes {
+    // The index of `local` among the captures, adding it the first time.
+    fn capture(&mut self, local: Local) -> usize {
+        if let Some(index) = self.captures.iter().position(|capture| *capture == local) {
+            return index;
+        }
+        self.captures.push(local);
+        return self.captures.len() - 1;
+    }
+}
+
+// Static pass run between parsing and interpreting. It gives every local a
+// slot in its function's frame, records on each declaration which slot that
+// is, and on each variable use whether it is in the frame or captured from an
+// enclosing function, leaving anything it cannot find to the globals. Each
+// function declaration gets the list of locals it captures.
+#[derive(Debug)]
+pub struct Resolver {
+    // Innermost last. The first is top-level code, whose blocks have a frame
+    // of their own.
+    functions: Vec<FunctionScopes>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    errors: Vec<String>
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            functions: vec![FunctionScopes::default()],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            errors: Vec::new()
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &mut [Stmt]) -> Result<(), Vec<String>> {
+        self.resolve_statements(statements);
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        return Err(std::mem::take(&mut self.errors));
+    }
+
+    fn resolve_statements(&mut self, statements: &mut [Stmt]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &mut Stmt) {
+        match statement {
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.end_scope();
+            },
+            Stmt::Class { name, superclass, methods, slot } => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+
+                *slot = self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    if let Expr::Variable { name: superclass_name, local: _ } = superclass {
+                        if superclass_name.lexeme == name.lexeme {
+                            self.error(superclass_name, "A class can't inherit from itself.");
+                        }
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_expression(superclass);
+                }
+
+                for method in methods {
+                    if let Stmt::Function { declaration, slot: _, captures } = method {
+                        let function_type = if declaration.name.lexeme == symbol::init() {
+                            FunctionType::Initializer
+                        } else {
+                            FunctionType::Method
+                        };
+                        *captures = self.resolve_function(Rc::make_mut(declaration), function_type);
+                    }
+                }
+
+                self.current_class = enclosing_class;
+            },
+            Stmt::Expression { expression } => {
+                self.resolve_expression(expression);
+            },
+            Stmt::Function { declaration, slot, captures } => {
+                *slot = self.declare(&declaration.name);
+                self.define(&declaration.name);
+                *captures = self.resolve_function(Rc::make_mut(declaration), FunctionType::Function);
+            },
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            },
+            Stmt::Print { expression } => {
+                self.resolve_expression(expression);
+            },
+            Stmt::Return { keyword, value } => {
+                if self.current_function == FunctionType::None {
+                    self.error(keyword, "Can't return from top-level code.");
+                }
+                if self.current_function == FunctionType::Initializer && *value != Expr::Null {
+                    self.error(keyword, "Can't return a value from an initializer.");
+                }
+                self.resolve_expression(value);
+            },
+            Stmt::Var { name, initializer, slot } => {
+                *slot = self.declare(name);
+                self.resolve_expression(initializer);
+                self.define(name);
+            },
+            Stmt::While { condition, body } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(body);
+            },
+            Stmt::ForIn { name, keyword: _, iterable, body, slot } => {
+                self.resolve_expression(iterable);
+                self.begin_scope();
+                *slot = self.declare(name);
+                self.define(name);
+                self.resolve_statement(body);
+                self.end_scope();
+            },
+            Stmt::Null => {}
+        }
+    }
+
+    fn resolve_expression(&mut self, expression: &mut Expr) {
+        match expression {
+            Expr::Assign { name, value, local } => {
+                self.resolve_expression(value);
+                *local = self.resolve_local(name);
+            },
+            Expr::Binary { left, operator: _, right } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            },
+            Expr::Call { callee, paren: _, arguments } => {
+                self.resolve_expression(callee);
+                for argument in arguments {
+                    self.resolve_expression(argument);
+                }
+            },
+            Expr::Get { object, name: _ } => {
+                self.resolve_expression(object);
+            },
+            Expr::Grouping { expression } => {
+                self.resolve_expression(expression);
+            },
+            Expr::Index { object, bracket: _, index } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+            },
+            Expr::List { elements } => {
+                for element in elements {
+                    self.resolve_expression(element);
+                }
+            },
+            Expr::Map { brace: _, entries } => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            },
+            Expr::Literal { value: _ } => {},
+            Expr::Logical { left, operator: _, right } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            },
+            Expr::Set { object, name: _, value } => {
+                self.resolve_expression(value);
+                self.resolve_expression(object);
+            },
+            Expr::SetIndex { object, bracket: _, index, value } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+                self.resolve_expression(value);
+            },
+            Expr::Slice { object, bracket: _, start, end } => {
+                self.resolve_expression(object);
+                self.resolve_expression(start);
+                self.resolve_expression(end);
+            },
+            Expr::Stringify { expression } => {
+                self.resolve_expression(expression);
+            },
+            Expr::Super { keyword, method: _, local, this } => {
+                match self.current_class {
+                    ClassType::None => self.error(keyword, "Can't use 'super' outside of a class."),
+                    ClassType::Class => self.error(keyword, "Can't use 'super' in a class with no superclass."),
+                    ClassType::Subclass => {}
+                }
+                *local = self.resolve_local(keyword);
+                *this = self.resolve_name(self.functions.len() - 1, &symbol::this());
+            },
+            Expr::This { keyword, local } => {
+                if self.current_class == ClassType::None {
+                    self.error(keyword, "Can't use 'this' outside of a class.");
+                    return;
+                }
+                *local = self.resolve_local(keyword);
+            },
+            Expr::Unary { operator: _, right } => {
+                self.resolve_expression(right);
+            },
+            Expr::Variable { name, local } => {
+                if let Some(scope) = self.function().scopes.last() {
+                    if scope.get(&name.lexeme).is_some_and(|variable| !variable.defined) {
+                        self.error(name, "Can't read local variable in its own initializer.");
+                    }
+                }
+                *local = self.resolve_local(name);
+            },
+            Expr::Null => {}
+        }
+    }
+
+    // Each function gets its own frame, numbered from 0. Methods find their
+    // receiver in slot 0 and, in a subclass, the superclass in slot 1, then
+    // the parameters. Returns what the function captures. The declaration
+    // comes straight from the parser, so `Rc::make_mut` never has to copy it.
+    fn resolve_function(&mut self, declaration: &mut FunctionDecl, function_type: FunctionType) -> Vec<Local> {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+
+        self.functions.push(FunctionScopes::default());
+        self.begin_scope();
+        if matches!(function_type, FunctionType::Method | FunctionType::Initializer) {
+            self.add_local(symbol::this(), true);
+            if self.current_class == ClassType::Subclass {
+                self.add_local(symbol::super_(), true);
+            }
+        }
+        for param in &declaration.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&mut declaration.body);
+        let function = self.functions.pop().expect("the function's scopes were just pushed");
+
+        self.current_function = enclosing_function;
+        return function.captures;
+    }
+
+    fn resolve_local(&mut self, name: &Token) -> Option<Local> {
+        return self.resolve_name(self.functions.len() - 1, &name.lexeme);
+    }
+
+    // Looks `name` up as seen from the function at `index`. A local of an
+    // enclosing function is captured by every function in between, so each
+    // one can hand it on to the closures it creates.
+    fn resolve_name(&mut self, index: usize, name: &Symbol) -> Option<Local> {
+        for scope in self.functions[index].scopes.iter().rev() {
+            if let Some(variable) = scope.get(name) {
+                return Some(Local::Slot(variable.slot));
+            }
+        }
+        if index == 0 {
+            return None;
+        }
+        let outer = self.resolve_name(index - 1, name)?;
+        return Some(Local::Captured(self.functions[index].capture(outer)));
+    }
+
+    fn function(&mut self) -> &mut FunctionScopes {
+        return self.functions.last_mut().expect("top-level scopes are never popped");
+    }
+
+    fn begin_scope(&mut self) {
+        self.function().scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        let function = self.function();
+        if let Some(scope) = function.scopes.pop() {
+            function.slots -= scope.len();
+        }
+    }
+
+    // Returns the new local's slot, or None for a global.
+    fn declare(&mut self, name: &Token) -> Option<usize> {
+        let already_declared = match self.function().scopes.last() {
+            Some(scope) => scope.contains_key(&name.lexeme),
+            None => return None
+        };
+        if already_declared {
+            self.error(name, "Already a variable with this name in this scope.");
+            return self.function().scopes.last().and_then(|scope| scope.get(&name.lexeme)).map(|variable| variable.slot);
+        }
+        return Some(self.add_local(name.lexeme.clone(), false));
+    }
+
+    fn add_local(&mut self, name: Symbol, defined: bool) -> usize {
+        let function = self.function();
+        let slot = function.slots;
+        if let Some(scope) = function.scopes.last_mut() {
+            scope.insert(name, Variable { slot: slot, defined: defined });
+            function.slots += 1;
+        }
+        return slot;
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(variable) = self.function().scopes.last_mut().and_then(|scope| scope.get_mut(&name.lexeme)) {
+            variable.defined = true;
+        }
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        if token.tokentype == TokenType::Eof {
+            self.errors.push(format!("[line {}] Error at end: {}", token.line, message));
+        } else {
+            self.errors.push(format!("[line {}] Error at '{}': {}", token.line, token.lexeme, message));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+    use crate::parser::Parser;
+
+    fn resolve_source(source: &str) -> Result<Vec<Stmt>, Vec<String>> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let mut parse = parser.parse().expect("source should parse");
+        Resolver::new().resolve(&mut parse)?;
+        Ok(parse)
+    }
+
+    #[test]
+    fn test_resolves_local_slot() {
+        let parse = resolve_source("{ var a = 1; { var b = 2; a; } }").unwrap();
+        let Stmt::Block { statements } = &parse[0] else { panic!("Expected a block") };
+        let Stmt::Block { statements } = &statements[1] else { panic!("Expected a block") };
+        let Stmt::Var { name: _, initializer: _, slot } = &statements[0] else { panic!("Expected a declaration") };
+        assert_eq!(*slot, Some(1));
+        let Stmt::Expression { expression: Expr::Variable { name: _, local } } = &statements[1] else {
+            panic!("Expected a variable expression")
+        };
+        assert_eq!(*local, Some(Local::Slot(0)));
+    }
+
+    #[test]
+    fn test_closed_scopes_free_their_slots() {
+        let parse = resolve_source("{ { var a = 1; } var b = 2; }").unwrap();
+        let Stmt::Block { statements } = &parse[0] else { panic!("Expected a block") };
+        let Stmt::Var { name: _, initializer: _, slot } = &statements[1] else { panic!("Expected a declaration") };
+        assert_eq!(*slot, Some(0));
+    }
+
+    #[test]
+    fn test_resolves_through_enclosing_functions() {
+        let parse = resolve_source("class A { m() { var x; fun f() { return this; } } }").unwrap();
+        let Stmt::Class { name: _, superclass: _, methods, slot: _ } = &parse[0] else { panic!("Expected a class") };
+        let Stmt::Function { declaration, slot: _, captures } = &methods[0] else { panic!("Expected a method") };
+        assert!(captures.is_empty());
+        // `this` takes slot 0 of the method's frame, so `x` gets 1 and `f` 2.
+        let Stmt::Function { declaration, slot: Some(2), captures } = &declaration.body[1] else { panic!("Expected f in slot 2") };
+        // `f` captures `this` alone, not `x`, and reads it through its capture.
+        assert_eq!(*captures, vec![Local::Slot(0)]);
+        let Stmt::Return { keyword: _, value: Expr::This { keyword: _, local } } = &declaration.body[0] else { panic!("Expected a return") };
+        assert_eq!(*local, Some(Local::Captured(0)));
+    }
+
+    #[test]
+    fn test_captures_pass_through_intermediate_functions() {
+        let parse = resolve_source("{ var a; var b; fun f() { fun g() { return b; } } }").unwrap();
+        let Stmt::Block { statements } = &parse[0] else { panic!("Expected a block") };
+        let Stmt::Function { declaration, slot: _, captures } = &statements[2] else { panic!("Expected f") };
+        assert_eq!(*captures, vec![Local::Slot(1)]);
+        let Stmt::Function { declaration: _, slot: _, captures } = &declaration.body[0] else { panic!("Expected g") };
+        assert_eq!(*captures, vec![Local::Captured(0)]);
+    }
+
+    #[test]
+    fn test_leaves_globals_unresolved() {
+        let parse = resolve_source("var a = 1; a;").unwrap();
+        let Stmt::Var { name: _, initializer: _, slot } = &parse[0] else { panic!("Expected a declaration") };
+        assert_eq!(*slot, None);
+        let Stmt::Expression { expression: Expr::Variable { name: _, local } } = &parse[1] else {
+            panic!("Expected a variable expression")
+        };
+        assert_eq!(*local, None);
+    }
+
+    #[test]
+    fn test_read_local_in_own_initializer() {
+        let errors = resolve_source("{ var a = a; }").unwrap_err();
+        assert_eq!(errors, vec!["[line 1] Error at 'a': Can't read local variable in its own initializer."]);
+    }
+
+    #[test]
+    fn test_duplicate_declaration_in_block() {
+        let errors = resolve_source("{ var a = 1; var a = 2; }").unwrap_err();
+        assert_eq!(errors, vec!["[line 1] Error at 'a': Already a variable with this name in this scope."]);
+    }
+
+    #[test]
+    fn test_global_redeclaration_is_allowed() {
+        assert!(resolve_source("var a = 1; var a = 2;").is_ok());
+    }
+
+    #[test]
+    fn test_return_at_top_level() {
+        let errors = resolve_source("return 1;").unwrap_err();
+        assert_eq!(errors, vec!["[line 1] Error at 'return': Can't return from top-level code."]);
+    }
+
+    #[test]
+    fn test_this_outside_class() {
+        let errors = resolve_source("this;").unwrap_err();
+        assert_eq!(errors, vec!["[line 1] Error at 'this': Can't use 'this' outside of a class."]);
+    }
+
+    #[test]
+    fn test_return_value_from_initializer() {
+        let errors = resolve_source("class Foo { init() { return 1; } }").unwrap_err();
+        assert_eq!(errors, vec!["[line 1] Error at 'return': Can't return a value from an initializer."]);
+    }
+
+    #[test]
+    fn test_class_inherits_from_itself() {
+        let errors = resolve_source("class Foo < Foo {}").unwrap_err();
+        assert_eq!(errors, vec!["[line 1] Error at 'Foo': A class can't inherit from itself."]);
+    }
+
+    #[test]
+    fn test_super_outside_class() {
+        let errors = resolve_source("super.method();").unwrap_err();
+        assert_eq!(errors, vec!["[line 1] Error at 'super': Can't use 'super' outside of a class."]);
+    }
+
+    #[test]
+    fn test_super_without_superclass() {
+        let errors = resolve_source("class Foo { bar() { super.bar(); } }").unwrap_err();
+        assert_eq!(errors, vec!["[line 1] Error at 'super': Can't use 'super' in a class with no superclass."]);
+    }
+
+    #[test]
+    fn test_reports_every_error() {
+        let errors = resolve_source("return 1;\n{ var a = 1; var a = 2; }").unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+}
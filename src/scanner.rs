@@ -1,21 +1,50 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
 use std::collections::HashMap;
 use crate::token::{Token};
+use crate::symbol::Symbol;
 use std::fmt;
 
 #[derive(Debug)]
 pub struct Scanner {
-    source: String,
+    source: Vec<char>,
     tokens: Vec<Token>,
+    errors: Vec<ScanError>,
     start: usize,
     current: usize,
     line: usize,
+    // Offset of the first character on the current line, used for columns.
+    line_start: usize,
+    // Where the token being scanned began; a string can span several lines.
+    start_line: usize,
+    start_column: usize,
+    // One entry per `${` still open, counting the `{` opened inside that
+    // expression so its own `}` can be told apart from the closing one.
+    interpolations: Vec<usize>,
     keywords: HashMap<String, TokenType>,
 }
 
+// A character the scanner couldn't turn into a token. Scanning carries on past
+// it so every bad character in the source is reported in one go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub line: usize,
+    pub column: usize,
+    pub character: char,
+    pub message: String
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}, column {}] Error at '{}': {}", self.line, self.column, self.character, self.message)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literals {
-    Identifier(String),
-    String(String),
+    #[allow(dead_code)]
+    Identifier(Symbol),
+    String(Symbol),
     Number(f64),
     Null
 }
@@ -27,6 +56,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -34,6 +66,7 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Percent,
 
     // One or two character tokens.
     Bang,
@@ -45,9 +78,10 @@ pub enum TokenType {
     Less,
     LessEqual,
 
-    // Literals.
+    // Literals. An Interpolation is the part of a string before a `${`.
     Identifier,
     String,
+    Interpolation,
     Number,
 
     // Keywords.
@@ -58,6 +92,7 @@ pub enum TokenType {
     Fun,
     For,
     If,
+    In,
     Nil,
     Or,
     Print,
@@ -88,37 +123,70 @@ impl fmt::Display for Literals {
 impl Scanner {
     pub fn new(source: String) -> Self {
         Self 
-        {   source: source, 
+        {   source: source.chars().collect(), 
             tokens: Vec::new(),
+            errors: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            interpolations: Vec::new(),
             keywords: Scanner::initialize_keywords()
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Vec<Token> {
+    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, Vec<ScanError>> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column_of(self.current);
             self.scan_token()
         }
 
         let token = Token::new(
             TokenType::Eof,
-            "".to_string(),
+            "",
             Literals::Null,
-            self.line
+            self.line,
+            self.column_of(self.current),
+            self.current
         );
 
         self.tokens.push(token);
-        return self.tokens.clone();
+        if !self.interpolations.is_empty() {
+            self.error('$', "Unterminated string interpolation.");
+        }
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
+        }
+        return Ok(self.tokens.clone());
+    }
+
+    fn column_of(&self, offset: usize) -> usize {
+        return offset - self.line_start + 1;
+    }
+
+    fn error(&mut self, character: char, message: &str) {
+        self.errors.push(ScanError {
+            line: self.start_line,
+            column: self.start_column,
+            character: character,
+            message: message.to_string()
+        });
+    }
+
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     fn match_next(&mut self, expected: char) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.source.get((self.current)..self.current+1).unwrap() != &expected.to_string() {
+        if self.source[self.current] != expected {
             return false;
         }
         self.current += 1;
@@ -130,14 +198,33 @@ impl Scanner {
         match c {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
-            '{' => self.add_token(TokenType::LeftBrace),
-            '}' => self.add_token(TokenType::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace)
+            },
+            '}' => {
+                match self.interpolations.pop() {
+                    // This closes a `${`, so the string picks up again.
+                    Some(0) => self.string(),
+                    Some(depth) => {
+                        self.interpolations.push(depth - 1);
+                        self.add_token(TokenType::RightBrace)
+                    },
+                    None => self.add_token(TokenType::RightBrace)
+                }
+            },
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            ':' => self.add_token(TokenType::Colon),
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::Semicolon),
             '*' => self.add_token(TokenType::Star),
+            '%' => self.add_token(TokenType::Percent),
             '!' => {
             let token_type = if self.match_next('=') {
                 TokenType::BangEqual
@@ -172,17 +259,19 @@ impl Scanner {
             }   
             '/' => {
                 if self.match_next('/') {
-                    while self.peek() != '\n' && self.is_at_end() {
+                    while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
-                }  else {
+                } else if self.match_next('*') {
+                    self.block_comment();
+                } else {
                     self.add_token(TokenType::Slash)
                 }
             } 
             ' ' | '\r' | '\t' => {
             }
             '\n' => {
-                self.line += 1;
+                self.new_line();
             }
             '"' => {
                 self.string()
@@ -194,8 +283,7 @@ impl Scanner {
                 else if self.is_alpha(c) {
                     self.identifier()
                 } else  {
-                    eprintln!("Unexpected character.")
-
+                    self.error(c, "Unexpected character.");
                 }
             }
         }
@@ -209,7 +297,7 @@ impl Scanner {
 
     fn advance(&mut self) -> char {
         self.current += 1;
-        self.source.chars().nth(self.current - 1).unwrap_or('\0')
+        self.source.get(self.current - 1).copied().unwrap_or('\0')
     }
 
     fn add_token(&mut self, tokentype: TokenType) {
@@ -217,7 +305,7 @@ impl Scanner {
     }
 
     fn is_digit(&self, c: char) -> bool {
-        return c >= '0' && c <= '9';
+        return c.is_ascii_digit();
     }
 
     fn identifier(&mut self) {
@@ -225,9 +313,9 @@ impl Scanner {
             self.advance();
         }
 
-        let text = self.source.get(self.start..self.current).unwrap();
-        let mut token_type = self.keywords.get(&text.to_string());
-        if token_type ==  None {
+        let text: String = self.source[self.start..self.current].iter().collect();
+        let mut token_type = self.keywords.get(&text);
+        if token_type.is_none() {
             token_type = Some(&TokenType::Identifier);
         }
 
@@ -236,7 +324,7 @@ impl Scanner {
     }
 
     fn is_alpha(&self, c: char) -> bool{
-        return (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_';
+        return c.is_ascii_alphabetic() || c == '_';
     }
 
     fn is_alpha_numeric(&self, c: char) -> bool {
@@ -244,20 +332,85 @@ impl Scanner {
     }
 
 
+    // Skips a `/* ... */` comment, the opening `/*` already consumed. Comments
+    // nest, so commenting out code that has its own block comment works.
+    fn block_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.error('/', "Unterminated block comment.");
+                return;
+            }
+            let c = self.advance();
+            if c == '\n' {
+                self.new_line();
+            } else if c == '/' && self.match_next('*') {
+                depth += 1;
+            } else if c == '*' && self.match_next('/') {
+                depth -= 1;
+            }
+        }
+    }
+
+    // Scans string contents from just after the opening quote, or from just
+    // after the `}` that ends an interpolated expression.
     fn string(&mut self) {
+        let mut value = String::new();
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            let c = self.advance();
+            match c {
+                '\n' => {
+                    self.new_line();
+                    value.push(c);
+                },
+                '\\' => {
+                    if let Some(escaped) = self.escape() {
+                        value.push(escaped);
+                    }
+                },
+                '$' if self.peek() == '{' => {
+                    self.advance();
+                    self.add_token2(TokenType::Interpolation, Literals::String(Symbol::intern(&value)));
+                    self.interpolations.push(0);
+                    return;
+                },
+                _ => value.push(c)
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            eprintln!("Unterminated string.");
+            self.error('"', "Unterminated string.");
+            return;
         }
         self.advance();
-        let value: String = self.source.get(self.start+1..self.current-1).unwrap().to_string();
-        self.add_token2(TokenType::String, Literals::String(value));
+        self.add_token2(TokenType::String, Literals::String(Symbol::intern(&value)));
+    }
+
+    // The character an escape sequence stands for; the backslash has just
+    // been consumed. Bad escapes are reported at the backslash.
+    fn escape(&mut self) -> Option<char> {
+        if self.is_at_end() {
+            return None;
+        }
+        let line = self.line;
+        let column = self.column_of(self.current - 1);
+        let c = self.advance();
+        match c {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            '$' => Some('$'),
+            _ => {
+                if c == '\n' {
+                    self.new_line();
+                }
+                self.errors.push(ScanError { line, column, character: c, message: "Invalid escape sequence.".to_string() });
+                None
+            }
+        }
     }
 
     fn number(&mut self) {
@@ -272,15 +425,8 @@ impl Scanner {
             } 
         }
 
-        let value = match self.source.get(self.start..self.current) {
-            Some(value) => {
-                value.parse::<f64>().unwrap()
-            },
-            None => {
-                eprintln!("Error Number");
-                0.0
-            }
-        };
+        let text: String = self.source[self.start..self.current].iter().collect();
+        let value = text.parse::<f64>().unwrap();
         self.add_token2(TokenType::Number, Literals::Number(value))
     }
 
@@ -288,26 +434,28 @@ impl Scanner {
         if self.is_at_end() {
             return '\0';
         } 
-        return self.source.chars().nth(self.current).unwrap();
+        return self.source[self.current];
     }
 
     fn peek_next(&self) -> char {
         if self.current + 1 >= self.source.len() {
             return '\0';
         } else {
-            return self.source.chars().nth(self.current + 1).unwrap();
+            return self.source[self.current + 1];
         }
     }
 
 
     fn add_token2(&mut self, tokentype: TokenType, literal: Literals) {
-        let text = self.source.get(self.start..self.current).unwrap();
+        let text: String = self.source[self.start..self.current].iter().collect();
         self.tokens.push(
             Token{
                 tokentype: tokentype,
-                lexeme: text.to_string(),
+                lexeme: Symbol::intern(&text),
                 literal: literal,
-                line: self.line, 
+                line: self.start_line, 
+                column: self.start_column,
+                offset: self.start,
             }
         );
     }
@@ -325,6 +473,7 @@ impl Scanner {
         keywords.insert("for".to_string(),    TokenType::For);
         keywords.insert("fun".to_string(),    TokenType::Fun);
         keywords.insert("if".to_string(),     TokenType::If);
+        keywords.insert("in".to_string(),     TokenType::In);
         keywords.insert("nil".to_string(),    TokenType::Nil);
         keywords.insert("or".to_string(),     TokenType::Or);
         keywords.insert("print".to_string(),  TokenType::Print);
@@ -338,3 +487,129 @@ impl Scanner {
         keywords
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_positions() {
+        let mut scanner = Scanner::new("var a = 1;\n  print a;".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+
+        let positions: Vec<(&str, usize, usize, usize)> = tokens.iter()
+            .map(|token| (token.lexeme.as_str(), token.line, token.column, token.offset))
+            .collect();
+        assert_eq!(positions, vec![
+            ("var", 1, 1, 0),
+            ("a", 1, 5, 4),
+            ("=", 1, 7, 6),
+            ("1", 1, 9, 8),
+            (";", 1, 10, 9),
+            ("print", 2, 3, 13),
+            ("a", 2, 9, 19),
+            (";", 2, 10, 20),
+            ("", 2, 11, 21),
+        ]);
+    }
+
+    #[test]
+    fn test_multiline_string_starts_on_first_line() {
+        let mut scanner = Scanner::new("\"a\nb\" x".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+
+        assert_eq!(tokens[0].literal, Literals::String(Symbol::intern("a\nb")));
+        assert_eq!((tokens[0].line, tokens[0].column), (1, 1));
+        assert_eq!((tokens[1].line, tokens[1].column), (2, 4));
+    }
+
+    #[test]
+    fn test_reports_every_unexpected_character() {
+        let mut scanner = Scanner::new("var a = 1;\nvar b @ 2 # 3;".to_string());
+        let errors = scanner.scan_tokens().unwrap_err();
+
+        assert_eq!(errors, vec![
+            ScanError { line: 2, column: 7, character: '@', message: "Unexpected character.".to_string() },
+            ScanError { line: 2, column: 11, character: '#', message: "Unexpected character.".to_string() },
+        ]);
+        assert_eq!(errors[0].to_string(), "[line 2, column 7] Error at '@': Unexpected character.");
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let mut scanner = Scanner::new("print \"abc".to_string());
+        let errors = scanner.scan_tokens().unwrap_err();
+
+        assert_eq!(errors, vec![
+            ScanError { line: 1, column: 7, character: '"', message: "Unterminated string.".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        let mut scanner = Scanner::new(r#""a\tb\nc \"q\" \\ \$ \0""#.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(tokens[0].literal, Literals::String(Symbol::intern("a\tb\nc \"q\" \\ $ \0")));
+    }
+
+    #[test]
+    fn test_invalid_escape() {
+        let mut scanner = Scanner::new("var s = \"ok\";\nprint \"bad \\q and \\w\";".to_string());
+        let errors = scanner.scan_tokens().unwrap_err();
+        assert_eq!(errors, vec![
+            ScanError { line: 2, column: 12, character: 'q', message: "Invalid escape sequence.".to_string() },
+            ScanError { line: 2, column: 19, character: 'w', message: "Invalid escape sequence.".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_interpolation_tokens() {
+        let mut scanner = Scanner::new("\"a ${x + 1} b ${ {\"k\": \"}\"}[\"k\"] }\"".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let kinds: Vec<TokenType> = tokens.iter().map(|token| token.tokentype.clone()).collect();
+        assert_eq!(kinds, vec![
+            TokenType::Interpolation, TokenType::Identifier, TokenType::Plus, TokenType::Number,
+            TokenType::Interpolation, TokenType::LeftBrace, TokenType::String, TokenType::Colon, TokenType::String,
+            TokenType::RightBrace, TokenType::LeftBracket, TokenType::String, TokenType::RightBracket,
+            TokenType::String, TokenType::Eof,
+        ]);
+        assert_eq!(tokens[0].literal, Literals::String(Symbol::intern("a ")));
+        assert_eq!(tokens[4].literal, Literals::String(Symbol::intern(" b ")));
+        assert_eq!(tokens[13].literal, Literals::String(Symbol::intern("")));
+    }
+
+    #[test]
+    fn test_unterminated_interpolation() {
+        let mut scanner = Scanner::new("\"a ${x".to_string());
+        let errors = scanner.scan_tokens().unwrap_err();
+        assert_eq!(errors[0].message, "Unterminated string interpolation.");
+    }
+
+    #[test]
+    fn test_line_comment() {
+        let mut scanner = Scanner::new("1 // ignored @ \"\n2 // at end".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+        assert_eq!(lexemes, vec!["1", "2", ""]);
+        assert_eq!(tokens[1].line, 2);
+    }
+
+    #[test]
+    fn test_block_comments_nest() {
+        let mut scanner = Scanner::new("a /* one\n /* two\n */ still @ comment */ b /**/ c / d".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+        assert_eq!(lexemes, vec!["a", "b", "c", "/", "d", ""]);
+        assert_eq!((tokens[1].line, tokens[1].column), (3, 24));
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let mut scanner = Scanner::new("a\n  /* outer /* inner */\n".to_string());
+        let errors = scanner.scan_tokens().unwrap_err();
+        assert_eq!(errors, vec![
+            ScanError { line: 2, column: 3, character: '/', message: "Unterminated block comment.".to_string() },
+        ]);
+    }
+}
+
@@ -0,0 +1,159 @@
+#![allow(clippy::needless_return)]
+
+use crate::generate_ast::LiteralsAst;
+use crate::interpreter::RuntimeError;
+use crate::stdlib::{Globals, NativeState};
+use super::{boolean, string_arg};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+pub fn register(globals: &mut dyn Globals) {
+    globals.define_native("readLine", 0, read_line);
+    globals.define_native("readFile", 1, read_file);
+    globals.define_native("writeFile", 2, write_file);
+    globals.define_native("appendFile", 2, append_file);
+    globals.define_native("fileExists", 1, file_exists);
+    globals.define_native("args", 0, args);
+}
+
+// The command-line arguments that followed the script path, as a list of strings.
+fn args(state: &mut NativeState, _arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let args: Vec<LiteralsAst> = state.args.iter().map(|arg| LiteralsAst::Strings(arg.as_str().into())).collect();
+    return Ok(LiteralsAst::List(Rc::new(RefCell::new(args))));
+}
+
+// One line from stdin without its line ending, or nil once input runs out.
+fn read_line(_state: &mut NativeState, _arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let mut line = String::new();
+    let read = io::stdin().lock().read_line(&mut line)
+        .map_err(|err| RuntimeError::without_token(format!("readLine() failed: {}.", err)))?;
+    if read == 0 {
+        return Ok(LiteralsAst::Null);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    return Ok(LiteralsAst::Strings(line.into()));
+}
+
+fn read_file(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let path = string_arg(arguments, 0, "readFile")?;
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(LiteralsAst::Strings(contents.into())),
+        Err(err) => Err(RuntimeError::without_token(format!("readFile() couldn't read '{}': {}.", path, err)))
+    }
+}
+
+// Replaces the file's contents, creating it if needed.
+fn write_file(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let path = string_arg(arguments, 0, "writeFile")?;
+    let text = string_arg(arguments, 1, "writeFile")?;
+    match fs::write(path, text) {
+        Ok(()) => Ok(LiteralsAst::Null),
+        Err(err) => Err(RuntimeError::without_token(format!("writeFile() couldn't write '{}': {}.", path, err)))
+    }
+}
+
+fn append_file(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let path = string_arg(arguments, 0, "appendFile")?;
+    let text = string_arg(arguments, 1, "appendFile")?;
+    let result = fs::OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()));
+    match result {
+        Ok(()) => Ok(LiteralsAst::Null),
+        Err(err) => Err(RuntimeError::without_token(format!("appendFile() couldn't write '{}': {}.", path, err)))
+    }
+}
+
+fn file_exists(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let path = string_arg(arguments, 0, "fileExists")?;
+    return Ok(boolean(Path::new(path).is_file()));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, Lox, Value};
+    use std::env;
+    use std::io;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // A path in the temp directory unique to this test run.
+    fn temp_path(name: &str) -> PathBuf {
+        return env::temp_dir().join(format!("lox-io-{}-{}", std::process::id(), name));
+    }
+
+    fn string(value: &str) -> Value {
+        return Value::Strings(value.into());
+    }
+
+    #[test]
+    fn test_write_read_append() {
+        let path = temp_path("write_read_append.txt");
+        let mut lox = Lox::new();
+        lox.set_global("path", string(path.to_str().unwrap()));
+
+        assert_eq!(lox.run_source("fileExists(path);").unwrap(), Value::False);
+        lox.run_source("writeFile(path, \"one\\n\"); appendFile(path, \"two\");").unwrap();
+        assert_eq!(lox.run_source("fileExists(path);").unwrap(), Value::True);
+        assert_eq!(lox.run_source("readFile(path);").unwrap(), string("one\ntwo"));
+
+        lox.run_source("writeFile(path, \"replaced\");").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "replaced");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_append_creates_file() {
+        let path = temp_path("append_creates.txt");
+        let mut lox = Lox::new();
+        lox.set_global("path", string(path.to_str().unwrap()));
+        lox.run_source("appendFile(path, \"a\"); appendFile(path, \"b\");").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ab");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_missing_file() {
+        let path = temp_path("missing.txt");
+        let mut lox = Lox::new();
+        lox.set_global("path", string(path.to_str().unwrap()));
+        let error = lox.run_source("readFile(path);").unwrap_err().to_string();
+        assert!(error.starts_with(&format!("readFile() couldn't read '{}': ", path.display())), "{}", error);
+    }
+
+    #[test]
+    fn test_file_exists_is_false_for_directories() {
+        let mut lox = Lox::new();
+        lox.set_global("path", string(env::temp_dir().to_str().unwrap()));
+        assert_eq!(lox.run_source("fileExists(path);").unwrap(), Value::False);
+    }
+
+    #[test]
+    fn test_args() {
+        let mut lox = Lox::new();
+        assert_eq!(lox.run_source("len(args());").unwrap(), Value::Number(0.0));
+        lox.set_args(vec!["input.txt".to_string(), "--verbose".to_string()]);
+        assert_eq!(lox.run_source("args()[1];").unwrap(), string("--verbose"));
+        assert_eq!(Lox::new().run_source("len(args());").unwrap(), Value::Number(0.0));
+    }
+
+    #[test]
+    fn test_args_on_the_vm() {
+        let mut lox = Lox::with_backend(Backend::Vm, Box::new(io::sink()), Box::new(io::sink()));
+        lox.set_args(vec!["input.txt".to_string()]);
+        assert_eq!(lox.run_source("args();").unwrap().to_string(), "[input.txt]");
+    }
+
+    #[test]
+    fn test_argument_types_are_checked() {
+        let error = Lox::new().run_source("writeFile(\"x\", 1);").unwrap_err();
+        assert_eq!(error.to_string(), "writeFile() expects a string as argument 2.\n[line 1]");
+    }
+}
@@ -0,0 +1,117 @@
+#![allow(clippy::needless_return)]
+
+use crate::generate_ast::LiteralsAst;
+use crate::interpreter::RuntimeError;
+use crate::stdlib::{Globals, NativeState};
+use super::list_arg;
+
+pub fn register(globals: &mut dyn Globals) {
+    globals.define_native("push", 2, push);
+    globals.define_native("pop", 1, pop);
+}
+
+// Appends in place and returns the list's new length.
+fn push(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let items = list_arg(arguments, 0, "push")?;
+    items.borrow_mut().push(arguments[1].clone());
+    let length = items.borrow().len();
+    return Ok(LiteralsAst::Number(length as f64));
+}
+
+// Removes and returns the last element.
+fn pop(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let items = list_arg(arguments, 0, "pop")?;
+    let last = items.borrow_mut().pop();
+    match last {
+        Some(item) => Ok(item),
+        None => Err(RuntimeError::without_token("pop() on an empty list.".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Lox, Value};
+    use crate::test_support::{error, output};
+
+    #[test]
+    fn test_list_literal() {
+        assert_eq!(output("print [1, \"two\", nil, [3]]; print [];"), "[1, two, nil, [3]]\n[]\n");
+    }
+
+    #[test]
+    fn test_index_get_and_set() {
+        assert_eq!(output("var xs = [1, 2, 3]; xs[1] = xs[0] + xs[2]; print xs; print xs[1];"), "[1, 4, 3]\n4\n");
+        assert_eq!(output("var grid = [[1, 2], [3, 4]]; grid[1][0] = 9; print grid;"), "[[1, 2], [9, 4]]\n");
+        assert_eq!(output("print \"héllo\"[1];"), "é\n");
+    }
+
+    #[test]
+    fn test_lists_are_shared() {
+        assert_eq!(output("var a = [1]; var b = a; push(b, 2); print a;"), "[1, 2]\n");
+    }
+
+    #[test]
+    fn test_bad_indices() {
+        assert_eq!(error("var xs = [1, 2];\nxs[2];"), "Index 2 is out of range for length 2.\n[line 2]");
+        assert_eq!(error("[1][-1];"), "Index must be a non-negative integer.\n[line 1]");
+        assert_eq!(error("[1][0.5];"), "Index must be a non-negative integer.\n[line 1]");
+        assert_eq!(error("[1][\"0\"];"), "Index must be a non-negative integer.\n[line 1]");
+        assert_eq!(error("var xs = []; xs[0] = 1;"), "Index 0 is out of range for length 0.\n[line 1]");
+        assert_eq!(error("1[0];"), "Only lists, strings and maps can be indexed.\n[line 1]");
+        assert_eq!(error("var s = \"ab\"; s[0] = \"c\";"), "Only lists and maps support index assignment.\n[line 1]");
+    }
+
+    #[test]
+    fn test_len_push_pop() {
+        assert_eq!(output("var xs = []; print push(xs, 1); push(xs, 2); print len(xs); print pop(xs); print xs;"), "1\n2\n2\n[1]\n");
+        assert_eq!(error("pop([]);"), "pop() on an empty list.\n[line 1]");
+        assert_eq!(error("push(1, 2);"), "push() expects a list as argument 1.\n[line 1]");
+        assert_eq!(error("len(1);"), "len() expects a string, a list or a map.\n[line 1]");
+    }
+
+    #[test]
+    fn test_slicing() {
+        assert_eq!(output("var xs = [0, 1, 2, 3, 4]; print xs[1:3]; print xs[:2]; print xs[3:]; print xs[:]; print xs[2:2];"),
+            "[1, 2]\n[0, 1]\n[3, 4]\n[0, 1, 2, 3, 4]\n[]\n");
+        assert_eq!(output("print \"lox script\"[4:];"), "script\n");
+        assert_eq!(output("var xs = [1, 2]; var ys = xs[:]; push(ys, 3); print xs;"), "[1, 2]\n");
+    }
+
+    #[test]
+    fn test_bad_slices() {
+        assert_eq!(error("[1, 2][0:3];"), "Slice bound 3 is out of range for length 2.\n[line 1]");
+        assert_eq!(error("[1, 2][2:1];"), "Slice start 2 is after its end 1.\n[line 1]");
+        assert_eq!(error("[1, 2][\"a\":];"), "Slice bounds must be numbers.\n[line 1]");
+    }
+
+    #[test]
+    fn test_list_containing_itself() {
+        assert_eq!(output("var l = [1]; push(l, l); print l; print l == l;"), "[1, [...]]\ntrue\n");
+        assert_eq!(output("var a = [1]; push(a, a); var b = [1]; push(b, b); print a == b; push(b, 2); print a == b;"), "true\nfalse\n");
+        assert_eq!(output("var l = []; var m = [l, l]; print m;"), "[[], []]\n");
+    }
+
+    #[test]
+    fn test_for_in() {
+        assert_eq!(output("for (var x in [1, 2, 3]) print x * 10;"), "10\n20\n30\n");
+        assert_eq!(output("var total = 0; for (var x in [1, 2, 3]) { total = total + x; } print total;"), "6\n");
+        assert_eq!(output("for (var x in []) print x; print \"done\";"), "done\n");
+    }
+
+    #[test]
+    fn test_for_in_closures_capture_each_element() {
+        let source = "var fns = []; for (var x in [1, 2]) { fun f() { return x; } push(fns, f); } print fns[0](); print fns[1]();";
+        assert_eq!(output(source), "1\n2\n");
+    }
+
+    #[test]
+    fn test_for_in_needs_a_list() {
+        assert_eq!(error("for (var x in 3) print x;"), "Can only iterate over a list.\n[line 1]");
+    }
+
+    #[test]
+    fn test_list_value() {
+        let value = Lox::new().run_source("[1, 2][1];").unwrap();
+        assert_eq!(value, Value::Number(2.0));
+    }
+}
@@ -0,0 +1,101 @@
+#![allow(clippy::needless_return)]
+
+use crate::generate_ast::LiteralsAst;
+use crate::interpreter::RuntimeError;
+use crate::stdlib::{Globals, NativeState};
+use super::{boolean, key_arg, map_arg};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub fn register(globals: &mut dyn Globals) {
+    globals.define_native("keys", 1, keys);
+    globals.define_native("values", 1, values);
+    globals.define_native("has", 2, has);
+    globals.define_native("remove", 2, remove);
+}
+
+// Both come back in insertion order as a new list.
+fn keys(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let map = map_arg(arguments, 0, "keys")?;
+    let keys = map.borrow().keys();
+    return Ok(LiteralsAst::List(Rc::new(RefCell::new(keys))));
+}
+
+fn values(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let map = map_arg(arguments, 0, "values")?;
+    let values = map.borrow().values();
+    return Ok(LiteralsAst::List(Rc::new(RefCell::new(values))));
+}
+
+fn has(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let map = map_arg(arguments, 0, "has")?;
+    let key = key_arg(arguments, 1, "has")?;
+    let found = map.borrow().contains(&key);
+    return Ok(boolean(found));
+}
+
+// Returns the removed value, or nil when the key wasn't there.
+fn remove(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let map = map_arg(arguments, 0, "remove")?;
+    let key = key_arg(arguments, 1, "remove")?;
+    let removed = map.borrow_mut().remove(&key);
+    return Ok(removed.unwrap_or(LiteralsAst::Null));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{error, output};
+
+    #[test]
+    fn test_map_literal() {
+        assert_eq!(output("var m = {\"a\": 1, \"b\": 2}; print m; print {};"), "{\"a\": 1, \"b\": 2}\n{}\n");
+        assert_eq!(output("print {1: \"one\", true: \"yes\", nil: \"none\"};"), "{1: one, true: yes, nil: none}\n");
+    }
+
+    #[test]
+    fn test_map_statement_is_not_a_block() {
+        assert_eq!(output("{\"a\": 1};\n{ print \"block\"; }\n{}"), "block\n");
+        assert_eq!(output("{-1: 2};\n{1 + 2: \"x\"};\n{\"a\" + \"b\": 1};\n{ {\"c\": 3}; print \"nested\"; }"), "nested\n");
+    }
+
+    #[test]
+    fn test_lookup_and_assignment() {
+        assert_eq!(output("var m = {\"a\": 1}; m[\"b\"] = 2; m[\"a\"] = m[\"a\"] + 10; print m; print m[\"b\"];"), "{\"a\": 11, \"b\": 2}\n2\n");
+        assert_eq!(output("var counts = {}; for (var w in [\"x\", \"y\", \"x\"]) { if (has(counts, w)) counts[w] = counts[w] + 1; else counts[w] = 1; } print counts;"),
+            "{\"x\": 2, \"y\": 1}\n");
+    }
+
+    #[test]
+    fn test_keys_follow_equality() {
+        assert_eq!(output("var m = {}; m[1] = \"a\"; m[1.0] = \"b\"; m[\"1\"] = \"c\"; m[-0] = \"z\"; print m;"), "{1: b, \"1\": c, 0: z}\n");
+        assert_eq!(output("var m = {0: \"zero\"}; print m[-0];"), "zero\n");
+    }
+
+    #[test]
+    fn test_keys_values_has_remove() {
+        let source = "var m = {\"a\": 1, \"b\": 2, \"c\": 3}; print keys(m); print values(m); print has(m, \"b\"); print remove(m, \"b\"); print has(m, \"b\"); print remove(m, \"b\"); print len(m);";
+        assert_eq!(output(source), "[a, b, c]\n[1, 2, 3]\ntrue\n2\nfalse\nnil\n2\n");
+    }
+
+    #[test]
+    fn test_map_equality() {
+        assert_eq!(output("print {\"a\": 1, \"b\": 2} == {\"b\": 2, \"a\": 1}; print {\"a\": 1} == {\"a\": 2};"), "true\nfalse\n");
+    }
+
+    #[test]
+    fn test_map_containing_itself() {
+        assert_eq!(output("var m = {}; m[\"self\"] = m; print m; print m == m;"), "{\"self\": {...}}\ntrue\n");
+        assert_eq!(output("var a = {}; a[1] = a; var b = {}; b[1] = b; print a == b; b[2] = 2; print a == b;"), "true\nfalse\n");
+        assert_eq!(output("var m = {}; var l = [m]; m[\"l\"] = l; print l;"), "[{\"l\": [...]}]\n");
+    }
+
+    #[test]
+    fn test_map_errors() {
+        assert_eq!(error("var m = {\"a\": 1};\nm[\"b\"];"), "Key 'b' not found in map.\n[line 2]");
+        assert_eq!(error("var m = {[1]: 2};"), "Map keys must be strings, numbers, booleans or nil.\n[line 1]");
+        assert_eq!(error("var m = {}; m[0 / 0] = 1;"), "Map keys must be strings, numbers, booleans or nil.\n[line 1]");
+        assert_eq!(error("has({}, []);"), "has() expects a string, number, boolean or nil key as argument 2.\n[line 1]");
+        assert_eq!(error("keys([]);"), "keys() expects a map as argument 1.\n[line 1]");
+        assert_eq!(error("var m = {}; m[0:1];"), "Only lists and strings can be sliced.\n[line 1]");
+    }
+}
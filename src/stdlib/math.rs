@@ -0,0 +1,223 @@
+#![allow(clippy::needless_return)]
+
+use crate::generate_ast::LiteralsAst;
+use crate::interpreter::RuntimeError;
+use crate::stdlib::{Globals, NativeState};
+use super::number_arg;
+use std::f64::consts;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn register(globals: &mut dyn Globals) {
+    globals.define_global("PI", LiteralsAst::Number(consts::PI));
+    globals.define_native("sqrt", 1, sqrt);
+    globals.define_native("pow", 2, pow);
+    globals.define_native("floor", 1, floor);
+    globals.define_native("ceil", 1, ceil);
+    globals.define_native("round", 1, round);
+    globals.define_native("abs", 1, abs);
+    globals.define_native("min", 2, min);
+    globals.define_native("max", 2, max);
+    globals.define_native("sin", 1, sin);
+    globals.define_native("cos", 1, cos);
+    globals.define_native("tan", 1, tan);
+    globals.define_native("log", 1, log);
+    globals.define_native("exp", 1, exp);
+    globals.define_native("random", 0, random);
+    globals.define_native("seed", 1, seed);
+}
+
+// Out-of-domain input such as sqrt(-1) gives NaN rather than an error, the
+// same as dividing by zero gives infinity.
+fn unary(arguments: &[LiteralsAst], function: &str, operation: fn(f64) -> f64) -> Result<LiteralsAst, RuntimeError> {
+    let value = number_arg(arguments, 0, function)?;
+    return Ok(LiteralsAst::Number(operation(value)));
+}
+
+fn binary(arguments: &[LiteralsAst], function: &str, operation: fn(f64, f64) -> f64) -> Result<LiteralsAst, RuntimeError> {
+    let left = number_arg(arguments, 0, function)?;
+    let right = number_arg(arguments, 1, function)?;
+    return Ok(LiteralsAst::Number(operation(left, right)));
+}
+
+fn sqrt(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "sqrt", f64::sqrt);
+}
+
+fn pow(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return binary(arguments, "pow", f64::powf);
+}
+
+fn floor(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "floor", f64::floor);
+}
+
+fn ceil(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "ceil", f64::ceil);
+}
+
+// Halfway cases round away from zero.
+fn round(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "round", f64::round);
+}
+
+fn abs(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "abs", f64::abs);
+}
+
+fn min(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return binary(arguments, "min", f64::min);
+}
+
+fn max(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return binary(arguments, "max", f64::max);
+}
+
+fn sin(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "sin", f64::sin);
+}
+
+fn cos(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "cos", f64::cos);
+}
+
+fn tan(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "tan", f64::tan);
+}
+
+// Natural logarithm.
+fn log(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "log", f64::ln);
+}
+
+fn exp(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return unary(arguments, "exp", f64::exp);
+}
+
+// Mixes the seed so nearby seeds give unrelated sequences, and never yields
+// the all-zero state xorshift can't leave.
+fn scramble(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    if z == 0 {
+        return 1;
+    }
+    return z;
+}
+
+// A number in [0, 1). Unseeded, the sequence starts from the current time;
+// after seed(n) it is the same on every run.
+fn random(state: &mut NativeState, _arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let mut x = state.random_state;
+    if x == 0 {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_nanos() as u64).unwrap_or(0);
+        x = scramble(nanos);
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    state.random_state = x;
+    let value = x.wrapping_mul(0x2545_F491_4F6C_DD1D);
+    // The top 53 bits fill an f64 mantissa exactly.
+    return Ok(LiteralsAst::Number((value >> 11) as f64 / (1u64 << 53) as f64));
+}
+
+fn seed(state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let value = number_arg(arguments, 0, "seed")?;
+    state.random_state = scramble(value.to_bits());
+    return Ok(LiteralsAst::Null);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Lox, Value};
+
+    fn number(source: &str) -> f64 {
+        match Lox::new().run_source(source).unwrap() {
+            Value::Number(value) => value,
+            value => panic!("Expected a number, got {:?}", value)
+        }
+    }
+
+    #[test]
+    fn test_modulo_operator() {
+        assert_eq!(number("7 % 3;"), 1.0);
+        assert_eq!(number("-7 % 3;"), -1.0);
+        assert_eq!(number("5.5 % 2;"), 1.5);
+        assert_eq!(number("1 + 10 % 4 * 2;"), 5.0);
+    }
+
+    #[test]
+    fn test_modulo_needs_numbers() {
+        let error = Lox::new().run_source("\"a\" % 2;").unwrap_err();
+        assert_eq!(error.to_string(), "Operands must be numbers.\n[line 1]");
+    }
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(number("floor(2.7);"), 2.0);
+        assert_eq!(number("ceil(2.1);"), 3.0);
+        assert_eq!(number("round(2.5);"), 3.0);
+        assert_eq!(number("round(-2.5);"), -3.0);
+        assert_eq!(number("abs(-4);"), 4.0);
+    }
+
+    #[test]
+    fn test_powers_and_logs() {
+        assert_eq!(number("sqrt(16);"), 4.0);
+        assert_eq!(number("pow(2, 10);"), 1024.0);
+        assert_eq!(number("exp(0);"), 1.0);
+        assert_eq!(number("log(exp(2));"), 2.0);
+        assert!(number("sqrt(-1);").is_nan());
+    }
+
+    #[test]
+    fn test_min_max() {
+        assert_eq!(number("min(3, -1);"), -1.0);
+        assert_eq!(number("max(3, -1);"), 3.0);
+    }
+
+    #[test]
+    fn test_trigonometry() {
+        assert_eq!(number("PI;"), std::f64::consts::PI);
+        assert!((number("sin(PI / 2);") - 1.0).abs() < 1e-12);
+        assert!((number("cos(PI);") + 1.0).abs() < 1e-12);
+        assert!((number("tan(PI / 4);") - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_seeded_random_repeats() {
+        let sequence = "seed(42); var a = random(); var b = random(); a * 1000 + b;";
+        assert_eq!(number(sequence), number(sequence));
+        assert_ne!(number("seed(1); random();"), number("seed(2); random();"));
+    }
+
+    #[test]
+    fn test_random_range() {
+        let mut lox = Lox::new();
+        lox.run_source("seed(7);").unwrap();
+        for _ in 0..1000 {
+            let Value::Number(value) = lox.run_source("random();").unwrap() else { panic!("Expected a number") };
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn test_seed_is_per_session() {
+        let mut seeded = Lox::new();
+        let mut other = Lox::new();
+        seeded.run_source("seed(3);").unwrap();
+        let first = seeded.run_source("random();").unwrap();
+        other.run_source("seed(4); random();").unwrap();
+        seeded.run_source("seed(3);").unwrap();
+        other.run_source("random();").unwrap();
+        assert_eq!(seeded.run_source("random();").unwrap(), first);
+    }
+
+    #[test]
+    fn test_argument_types_are_checked() {
+        let error = Lox::new().run_source("pow(2, \"x\");").unwrap_err();
+        assert_eq!(error.to_string(), "pow() expects a number as argument 2.\n[line 1]");
+    }
+}
@@ -0,0 +1,145 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use crate::callable::{NativeFn, NativeFunction};
+use crate::generate_ast::LiteralsAst;
+use crate::interpreter::RuntimeError;
+use crate::map::{LoxMap, MapKey};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod io;
+mod lists;
+mod maps;
+mod math;
+mod strings;
+
+// What natives keep between calls. The interpreter and the VM each own one,
+// so two sessions on the same thread never see each other's state.
+#[derive(Debug, Default)]
+pub struct NativeState {
+    // What args() returns: the command-line arguments after the script path.
+    pub args: Vec<String>,
+    // xorshift64* state for random(); zero means "not seeded yet".
+    pub random_state: u64
+}
+
+// Somewhere natives can be installed: the tree-walking interpreter or the VM.
+pub trait Globals {
+    fn define_global(&mut self, name: &str, value: LiteralsAst);
+
+    // Exposes a Rust function to scripts as a global called `name`.
+    fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let native = NativeFunction { name: name.to_string(), arity: arity, function: function };
+        self.define_global(name, LiteralsAst::Native(Rc::new(native)));
+    }
+}
+
+// Native functions every interpreter starts with.
+pub fn register(globals: &mut dyn Globals) {
+    globals.define_native("clock", 0, clock);
+    globals.define_native("len", 1, len);
+    strings::register(globals);
+    lists::register(globals);
+    maps::register(globals);
+    io::register(globals);
+    math::register(globals);
+}
+
+// Seconds since the Unix epoch, for timing scripts.
+fn clock(_state: &mut NativeState, _arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_err(|err| RuntimeError::without_token(err.to_string()))?;
+    return Ok(LiteralsAst::Number(now.as_secs_f64()));
+}
+
+// The number of characters in a string, elements in a list or entries in a map.
+fn len(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let length = match &arguments[0] {
+        LiteralsAst::Strings(value) => value.chars().count(),
+        LiteralsAst::List(items) => items.borrow().len(),
+        LiteralsAst::Map(map) => map.borrow().len(),
+        _ => return Err(RuntimeError::without_token("len() expects a string, a list or a map.".to_string()))
+    };
+    return Ok(LiteralsAst::Number(length as f64));
+}
+
+// Argument checks shared by the natives. `position` is the zero-based index
+// into `arguments`; messages count from one like a reader would.
+pub(crate) fn string_arg<'a>(arguments: &'a [LiteralsAst], position: usize, function: &str) -> Result<&'a str, RuntimeError> {
+    match &arguments[position] {
+        LiteralsAst::Strings(value) => Ok(value),
+        _ => Err(RuntimeError::without_token(format!("{}() expects a string as argument {}.", function, position + 1)))
+    }
+}
+
+pub(crate) fn list_arg(arguments: &[LiteralsAst], position: usize, function: &str) -> Result<Rc<RefCell<Vec<LiteralsAst>>>, RuntimeError> {
+    match &arguments[position] {
+        LiteralsAst::List(items) => Ok(Rc::clone(items)),
+        _ => Err(RuntimeError::without_token(format!("{}() expects a list as argument {}.", function, position + 1)))
+    }
+}
+
+pub(crate) fn map_arg(arguments: &[LiteralsAst], position: usize, function: &str) -> Result<Rc<RefCell<LoxMap>>, RuntimeError> {
+    match &arguments[position] {
+        LiteralsAst::Map(map) => Ok(Rc::clone(map)),
+        _ => Err(RuntimeError::without_token(format!("{}() expects a map as argument {}.", function, position + 1)))
+    }
+}
+
+pub(crate) fn key_arg(arguments: &[LiteralsAst], position: usize, function: &str) -> Result<MapKey, RuntimeError> {
+    match MapKey::from_value(&arguments[position]) {
+        Some(key) => Ok(key),
+        None => Err(RuntimeError::without_token(format!(
+            "{}() expects a string, number, boolean or nil key as argument {}.", function, position + 1)))
+    }
+}
+
+pub(crate) fn number_arg(arguments: &[LiteralsAst], position: usize, function: &str) -> Result<f64, RuntimeError> {
+    match &arguments[position] {
+        LiteralsAst::Number(value) => Ok(*value),
+        _ => Err(RuntimeError::without_token(format!("{}() expects a number as argument {}.", function, position + 1)))
+    }
+}
+
+pub(crate) fn index_arg(arguments: &[LiteralsAst], position: usize, function: &str) -> Result<usize, RuntimeError> {
+    let value = number_arg(arguments, position, function)?;
+    if value < 0.0 || value.fract() != 0.0 || !value.is_finite() {
+        return Err(RuntimeError::without_token(format!("{}() expects a non-negative integer as argument {}.", function, position + 1)));
+    }
+    return Ok(value as usize);
+}
+
+pub(crate) fn boolean(value: bool) -> LiteralsAst {
+    if value {
+        return LiteralsAst::True;
+    }
+    return LiteralsAst::False;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Lox, Value};
+
+    #[test]
+    fn test_clock_returns_seconds() {
+        let mut lox = Lox::new();
+        let Value::Number(seconds) = lox.run_source("clock();").unwrap() else { panic!("Expected a number") };
+        assert!(seconds > 1_000_000_000.0);
+    }
+
+    #[test]
+    fn test_clock_measures_elapsed_time() {
+        let mut lox = Lox::new();
+        let value = lox.run_source("var start = clock(); var i = 0; while (i < 100) i = i + 1; clock() - start;").unwrap();
+        let Value::Number(elapsed) = value else { panic!("Expected a number") };
+        assert!(elapsed >= 0.0);
+    }
+
+    #[test]
+    fn test_clock_arity() {
+        let mut lox = Lox::new();
+        let error = lox.run_source("clock(1);").unwrap_err();
+        assert_eq!(error.to_string(), "Expected 0 arguments but got 1.\n[line 1]");
+    }
+}
@@ -0,0 +1,196 @@
+#![allow(clippy::needless_return)]
+
+use crate::generate_ast::LiteralsAst;
+use crate::interpreter::RuntimeError;
+use crate::stdlib::{Globals, NativeState};
+use super::{boolean, index_arg, number_arg, string_arg};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// String natives. Lengths and indices count characters, not bytes, so
+// non-ASCII text behaves the way a script author expects.
+pub fn register(globals: &mut dyn Globals) {
+    globals.define_native("substr", 3, substr);
+    globals.define_native("indexOf", 2, index_of);
+    globals.define_native("upper", 1, upper);
+    globals.define_native("lower", 1, lower);
+    globals.define_native("trim", 1, trim);
+    globals.define_native("split", 2, split);
+    globals.define_native("replace", 3, replace);
+    globals.define_native("startsWith", 2, starts_with);
+    globals.define_native("chr", 1, chr);
+    globals.define_native("ord", 1, ord);
+}
+
+// substr(string, start, length)
+fn substr(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let value = string_arg(arguments, 0, "substr")?;
+    let start = index_arg(arguments, 1, "substr")?;
+    let length = index_arg(arguments, 2, "substr")?;
+
+    let count = value.chars().count();
+    match start.checked_add(length) {
+        Some(end) if end <= count => {},
+        Some(end) => return Err(RuntimeError::without_token(format!(
+            "substr() range {}..{} is out of bounds for a string of length {}.", start, end, count))),
+        // Lengths too big for a usize come back saturated, so show the one given.
+        None => return Err(RuntimeError::without_token(format!(
+            "substr() length {} is out of bounds for a string of length {}.", arguments[2], count)))
+    }
+    return Ok(LiteralsAst::Strings(value.chars().skip(start).take(length).collect::<String>().into()));
+}
+
+// The character index of the first match, or -1.
+fn index_of(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let value = string_arg(arguments, 0, "indexOf")?;
+    let needle = string_arg(arguments, 1, "indexOf")?;
+    let index = match value.find(needle) {
+        Some(byte_index) => value[..byte_index].chars().count() as f64,
+        None => -1.0
+    };
+    return Ok(LiteralsAst::Number(index));
+}
+
+fn upper(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return Ok(LiteralsAst::Strings(string_arg(arguments, 0, "upper")?.to_uppercase().into()));
+}
+
+fn lower(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return Ok(LiteralsAst::Strings(string_arg(arguments, 0, "lower")?.to_lowercase().into()));
+}
+
+fn trim(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    return Ok(LiteralsAst::Strings(string_arg(arguments, 0, "trim")?.trim().into()));
+}
+
+// An empty separator splits the string into its characters.
+fn split(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let value = string_arg(arguments, 0, "split")?;
+    let separator = string_arg(arguments, 1, "split")?;
+    let parts: Vec<LiteralsAst> = if separator.is_empty() {
+        value.chars().map(|c| LiteralsAst::Strings(c.to_string().into())).collect()
+    } else {
+        value.split(separator).map(|part| LiteralsAst::Strings(part.into())).collect()
+    };
+    return Ok(LiteralsAst::List(Rc::new(RefCell::new(parts))));
+}
+
+fn replace(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let value = string_arg(arguments, 0, "replace")?;
+    let from = string_arg(arguments, 1, "replace")?;
+    let to = string_arg(arguments, 2, "replace")?;
+    if from.is_empty() {
+        return Err(RuntimeError::without_token("replace() can't replace an empty string.".to_string()));
+    }
+    return Ok(LiteralsAst::Strings(value.replace(from, to).into()));
+}
+
+fn starts_with(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let value = string_arg(arguments, 0, "startsWith")?;
+    let prefix = string_arg(arguments, 1, "startsWith")?;
+    return Ok(boolean(value.starts_with(prefix)));
+}
+
+// The one-character string for a Unicode code point.
+fn chr(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let code = number_arg(arguments, 0, "chr")?;
+    let character = if code >= 0.0 && code.fract() == 0.0 && code <= u32::MAX as f64 {
+        char::from_u32(code as u32)
+    } else {
+        None
+    };
+    match character {
+        Some(character) => Ok(LiteralsAst::Strings(character.to_string().into())),
+        None => Err(RuntimeError::without_token(format!("chr() argument {} is not a valid character code.", code)))
+    }
+}
+
+// The code point of a one-character string.
+fn ord(_state: &mut NativeState, arguments: &[LiteralsAst]) -> Result<LiteralsAst, RuntimeError> {
+    let value = string_arg(arguments, 0, "ord")?;
+    let mut characters = value.chars();
+    match (characters.next(), characters.next()) {
+        (Some(character), None) => Ok(LiteralsAst::Number(character as u32 as f64)),
+        _ => Err(RuntimeError::without_token("ord() expects a single-character string.".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Lox, Value};
+    use crate::test_support::error;
+
+    fn eval(source: &str) -> Value {
+        return Lox::new().run_source(source).unwrap();
+    }
+
+    fn string(value: &str) -> Value {
+        return Value::Strings(value.into());
+    }
+
+    #[test]
+    fn test_len_counts_characters() {
+        assert_eq!(eval("len(\"hello\");"), Value::Number(5.0));
+        assert_eq!(eval("len(\"héllo wörld\");"), Value::Number(11.0));
+        assert_eq!(eval("len(\"\");"), Value::Number(0.0));
+    }
+
+    #[test]
+    fn test_substr() {
+        assert_eq!(eval("substr(\"hello world\", 6, 5);"), string("world"));
+        assert_eq!(eval("substr(\"naïve\", 2, 3);"), string("ïve"));
+        assert_eq!(eval("substr(\"abc\", 3, 0);"), string(""));
+    }
+
+    #[test]
+    fn test_substr_out_of_range() {
+        assert_eq!(error("substr(\"abc\", 2, 5);"), "substr() range 2..7 is out of bounds for a string of length 3.\n[line 1]");
+        assert_eq!(error("substr(\"abc\", 1, pow(10, 20));"), "substr() length 100000000000000000000 is out of bounds for a string of length 3.\n[line 1]");
+        assert_eq!(error("substr(\"abc\", -1, 1);"), "substr() expects a non-negative integer as argument 2.\n[line 1]");
+        assert_eq!(error("substr(\"abc\", 0.5, 1);"), "substr() expects a non-negative integer as argument 2.\n[line 1]");
+    }
+
+    #[test]
+    fn test_index_of() {
+        assert_eq!(eval("indexOf(\"hello\", \"l\");"), Value::Number(2.0));
+        assert_eq!(eval("indexOf(\"añb\", \"b\");"), Value::Number(2.0));
+        assert_eq!(eval("indexOf(\"hello\", \"z\");"), Value::Number(-1.0));
+    }
+
+    #[test]
+    fn test_case_and_trim() {
+        assert_eq!(eval("upper(\"straße\");"), string("STRASSE"));
+        assert_eq!(eval("lower(\"ÀB\");"), string("àb"));
+        assert_eq!(eval("trim(\"  spaced \t\");"), string("spaced"));
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(eval("split(\"a,b,,c\", \",\");").to_string(), "[a, b, , c]");
+        assert_eq!(eval("split(\"hé\", \"\");").to_string(), "[h, é]");
+    }
+
+    #[test]
+    fn test_replace_and_starts_with() {
+        assert_eq!(eval("replace(\"a-b-c\", \"-\", \"+\");"), string("a+b+c"));
+        assert_eq!(eval("startsWith(\"lox script\", \"lox\");"), Value::True);
+        assert_eq!(eval("startsWith(\"lox\", \"script\");"), Value::False);
+        assert_eq!(error("replace(\"abc\", \"\", \"x\");"), "replace() can't replace an empty string.\n[line 1]");
+    }
+
+    #[test]
+    fn test_chr_and_ord() {
+        assert_eq!(eval("chr(65);"), string("A"));
+        assert_eq!(eval("chr(233);"), string("é"));
+        assert_eq!(eval("ord(\"é\");"), Value::Number(233.0));
+        assert_eq!(eval("chr(ord(\"z\"));"), string("z"));
+        assert_eq!(error("ord(\"ab\");"), "ord() expects a single-character string.\n[line 1]");
+        assert_eq!(error("chr(55296);"), "chr() argument 55296 is not a valid character code.\n[line 1]");
+    }
+
+    #[test]
+    fn test_argument_types_are_checked() {
+        assert_eq!(error("upper(1);"), "upper() expects a string as argument 1.\n[line 1]");
+        assert_eq!(error("substr(\"abc\", \"1\", 1);"), "substr() expects a number as argument 2.\n[line 1]");
+    }
+}
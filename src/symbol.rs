@@ -0,0 +1,172 @@
+#![allow(clippy::needless_return)]
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+// An interned string. Every occurrence of the same text gets the same
+// symbol, so comparing and hashing one is as cheap as for a pointer. The text
+// is freed along with the last symbol for it. Symbols belong to the interner
+// of the thread that made them, and `Rc` keeps them from leaving it.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+// Sweeping waits until the table holds at least this many texts.
+const MIN_SWEEP: usize = 256;
+
+// Holds a reference to every symbol's text so a new occurrence can find it.
+// Text only the table still refers to is swept out whenever the table has
+// doubled since the last sweep, which keeps it in proportion to the symbols
+// that are alive.
+struct Interner {
+    symbols: HashSet<Rc<str>>,
+    next_sweep: usize,
+    // Names the interpreter looks up itself, interned up front so it never
+    // has to hash them. Holding them here also keeps them from being swept.
+    this: Symbol,
+    super_: Symbol,
+    init: Symbol
+}
+
+impl Interner {
+    fn new() -> Self {
+        let mut symbols = HashSet::new();
+        let mut well_known = |text: &str| {
+            let text: Rc<str> = Rc::from(text);
+            symbols.insert(Rc::clone(&text));
+            return Symbol(text);
+        };
+        let this = well_known("this");
+        let super_ = well_known("super");
+        let init = well_known("init");
+        return Self { symbols, next_sweep: MIN_SWEEP, this, super_, init };
+    }
+
+    fn intern(&mut self, text: &str) -> Symbol {
+        if let Some(text) = self.symbols.get(text) {
+            return Symbol(Rc::clone(text));
+        }
+        if self.symbols.len() >= self.next_sweep {
+            self.symbols.retain(|text| Rc::strong_count(text) > 1);
+            self.next_sweep = MIN_SWEEP.max(self.symbols.len() * 2);
+        }
+        let text: Rc<str> = Rc::from(text);
+        self.symbols.insert(Rc::clone(&text));
+        return Symbol(text);
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+pub fn this() -> Symbol {
+    return INTERNER.with(|interner| interner.borrow().this.clone());
+}
+
+pub fn super_() -> Symbol {
+    return INTERNER.with(|interner| interner.borrow().super_.clone());
+}
+
+pub fn init() -> Symbol {
+    return INTERNER.with(|interner| interner.borrow().init.clone());
+}
+
+impl Symbol {
+    pub fn intern(text: &str) -> Symbol {
+        return INTERNER.with(|interner| interner.borrow_mut().intern(text));
+    }
+
+    pub fn as_str(&self) -> &str {
+        return &self.0;
+    }
+
+    // The interned text itself, for values that should share it.
+    pub fn text(&self) -> Rc<str> {
+        return Rc::clone(&self.0);
+    }
+}
+
+// Two live symbols with the same text share it, so the pointer is enough.
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        return Rc::ptr_eq(&self.0, &other.0);
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).cast::<u8>().hash(state);
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        return self.as_str();
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        return self.as_str() == other;
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        return self.as_str() == *other;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_text_gets_same_symbol() {
+        let a = Symbol::intern("counter");
+        assert_eq!(a, Symbol::intern(&format!("coun{}", "ter")));
+        assert_ne!(a, Symbol::intern("Counter"));
+        assert_eq!(a.as_str(), "counter");
+        assert_eq!(a, "counter");
+    }
+
+    #[test]
+    fn test_well_known_symbols() {
+        assert_eq!(Symbol::intern("this"), this());
+        assert_eq!(Symbol::intern("super"), super_());
+        assert_eq!(Symbol::intern("init"), init());
+        assert_eq!(init().to_string(), "init");
+    }
+
+    #[test]
+    fn test_unused_text_is_freed() {
+        let kept = Symbol::intern("kept");
+        let dropped = Rc::downgrade(&Symbol::intern("dropped").0);
+        for i in 0..MIN_SWEEP * 4 {
+            Symbol::intern(&format!("name{}", i));
+        }
+        assert!(dropped.upgrade().is_none());
+        assert_eq!(kept, Symbol::intern("kept"));
+        INTERNER.with(|interner| assert!(interner.borrow().symbols.len() <= MIN_SWEEP * 2));
+    }
+}
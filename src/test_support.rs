@@ -0,0 +1,41 @@
+#![allow(clippy::needless_return)]
+
+use crate::Lox;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// A writer the test keeps a second handle on, so it can read back what the
+// interpreter printed.
+#[derive(Clone, Default)]
+pub struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Captured {
+    pub fn contents(&self) -> String {
+        return String::from_utf8(self.0.borrow().clone()).unwrap();
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Runs `source` in a fresh session and returns what it printed.
+pub fn output(source: &str) -> String {
+    let captured = Captured::default();
+    let mut lox = Lox::with_output(Box::new(captured.clone()));
+    lox.run_source(source).unwrap();
+    return captured.contents();
+}
+
+// Runs `source` in a fresh session and returns the error that stopped it.
+pub fn error(source: &str) -> String {
+    return Lox::new().run_source(source).unwrap_err().to_string();
+}
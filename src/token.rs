@@ -1,19 +1,27 @@
 use crate::scanner::{TokenType, Literals};
+use crate::symbol::Symbol;
+use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub tokentype: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Literals,
     pub line: usize, 
+    // Where the token starts: 1-based column on its line and 0-based
+    // character offset into the source.
+    pub column: usize,
+    pub offset: usize,
 }
 
 impl Token {
-    pub fn new(tokentype: TokenType, lexeme: String, literal: Literals, line: usize) -> Self {
-        Self { tokentype, lexeme, literal, line }
+    pub fn new(tokentype: TokenType, lexeme: &str, literal: Literals, line: usize, column: usize, offset: usize) -> Self {
+        Self { tokentype, lexeme: Symbol::intern(lexeme), literal, line, column, offset }
     }
+}
 
-    pub fn to_string(&self) -> String {
-        format!("{:?} {:?} {:?}", self.tokentype, self.lexeme, self.literal)
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {:?} {:?}", self.tokentype, self.lexeme, self.literal)
     }
 }
\ No newline at end of file
@@ -0,0 +1,26 @@
+#![allow(clippy::needless_return)]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Debug tracing of the parser and interpreter internals. It is off unless the
+// CLI is started with `--trace`, and goes to stderr so it never mixes with
+// what a script prints.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    return ENABLED.load(Ordering::Relaxed);
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::trace::enabled() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use trace;
@@ -0,0 +1,167 @@
+#![allow(clippy::needless_return)]
+
+use crate::generate_ast::LiteralsAst;
+use crate::symbol::Symbol;
+use crate::vm::object::Function;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// One VM instruction. Operands are indexes into the chunk's constant, name or
+// function tables, stack slots, upvalue indexes, argument counts or jump
+// distances, all sized to keep an instruction within four bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    // The operand indexes the chunk's name table, here and for the other
+    // instructions that look something up by name.
+    GetGlobal(u16),
+    DefineGlobal(u16),
+    SetGlobal(u16),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    GetProperty(u16),
+    SetProperty(u16),
+    GetSuper(u16),
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Not,
+    Negate,
+    Stringify,
+    Print,
+    // Jumps are relative to the instruction after the jump.
+    Jump(u16),
+    JumpIfFalse(u16),
+    Loop(u16),
+    Call(u8),
+    // The operand indexes the chunk's function table.
+    Closure(u16),
+    CloseUpvalue,
+    Return,
+    Class(u16),
+    Inherit,
+    Method(u16),
+    // Builds a list from that many values, or a map from that many pairs.
+    List(u16),
+    Map(u16),
+    Index,
+    SetIndex,
+    Slice,
+    // Fails unless the value on top of the stack is a list.
+    IterList,
+    // Pushes the next element of the list in the given slot, whose position
+    // is kept in the slot after it, or jumps when there are none left.
+    IterNext(u8, u16),
+    // Top-level statements leave their value in a result register so a run
+    // can hand back the value of its last statement.
+    Result,
+    ClearResult,
+}
+
+// The constants `add_constant` can reuse. Numbers go by their bits, so 0 and
+// -0 stay apart.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    Strings(Rc<str>)
+}
+
+// A compiled body: instructions, the source line of each, and the values and
+// names the instructions refer to.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub lines: Vec<usize>,
+    pub constants: Vec<LiteralsAst>,
+    pub names: Vec<Symbol>,
+    pub functions: Vec<Rc<Function>>,
+    constant_indexes: HashMap<ConstantKey, usize>,
+    name_indexes: HashMap<Symbol, usize>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, op: OpCode, line: usize) -> usize {
+        self.code.push(op);
+        self.lines.push(line);
+        return self.code.len() - 1;
+    }
+
+    // Reuses an equal constant if there is one, so a string used all over a
+    // function takes a single slot.
+    pub fn add_constant(&mut self, value: LiteralsAst) -> usize {
+        let key = match &value {
+            LiteralsAst::Number(n) => Some(ConstantKey::Number(n.to_bits())),
+            LiteralsAst::Strings(text) => Some(ConstantKey::Strings(text.clone())),
+            _ => None
+        };
+        if let Some(index) = key.as_ref().and_then(|key| self.constant_indexes.get(key)) {
+            return *index;
+        }
+        self.constants.push(value);
+        let index = self.constants.len() - 1;
+        if let Some(key) = key {
+            self.constant_indexes.insert(key, index);
+        }
+        return index;
+    }
+
+    // Each name is stored once however often the chunk uses it.
+    pub fn add_name(&mut self, name: &Symbol) -> usize {
+        if let Some(index) = self.name_indexes.get(name) {
+            return *index;
+        }
+        self.names.push(name.clone());
+        let index = self.names.len() - 1;
+        self.name_indexes.insert(name.clone(), index);
+        return index;
+    }
+
+    pub fn add_function(&mut self, function: Function) -> usize {
+        self.functions.push(Rc::new(function));
+        return self.functions.len() - 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_constant_reuses_equal_values() {
+        let mut chunk = Chunk::new();
+        assert_eq!(chunk.add_constant(LiteralsAst::Strings("a".into())), 0);
+        assert_eq!(chunk.add_constant(LiteralsAst::Number(0.0)), 1);
+        assert_eq!(chunk.add_constant(LiteralsAst::Number(-0.0)), 2);
+        assert_eq!(chunk.add_constant(LiteralsAst::Strings("a".into())), 0);
+        assert_eq!(chunk.add_constant(LiteralsAst::Number(0.0)), 1);
+        assert_eq!(chunk.constants.len(), 3);
+    }
+
+    #[test]
+    fn test_add_name_reuses_names() {
+        let mut chunk = Chunk::new();
+        assert_eq!(chunk.add_name(&Symbol::intern("a")), 0);
+        assert_eq!(chunk.add_name(&Symbol::intern("b")), 1);
+        assert_eq!(chunk.add_name(&Symbol::intern("a")), 0);
+        assert_eq!(chunk.names.len(), 2);
+        assert!(chunk.constants.is_empty());
+    }
+}
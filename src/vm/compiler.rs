@@ -0,0 +1,621 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use crate::generate_ast::{Expr, LiteralsAst};
+use crate::parser::{FunctionDecl, Stmt};
+use crate::scanner::TokenType;
+use crate::symbol::Symbol;
+use crate::token::Token;
+use crate::vm::chunk::{Chunk, OpCode};
+use crate::vm::object::{Function, UpvalueCapture};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer
+}
+
+#[derive(Debug)]
+struct Local {
+    name: String,
+    depth: usize,
+    // Set when a closure captures the local, so leaving its scope moves the
+    // value into the upvalue instead of dropping it.
+    is_captured: bool
+}
+
+// Everything being built for one function. Slot 0 holds the function itself,
+// or `this` in a method, so parameters start at slot 1.
+#[derive(Debug)]
+struct FunctionState {
+    kind: FunctionKind,
+    name: String,
+    arity: usize,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueCapture>,
+    scope_depth: usize
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: &str, arity: usize) -> Self {
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => ""
+        };
+        Self {
+            kind: kind,
+            name: name.to_string(),
+            arity: arity,
+            chunk: Chunk::new(),
+            locals: vec![Local { name: slot_zero.to_string(), depth: 0, is_captured: false }],
+            upvalues: Vec::new(),
+            scope_depth: 0
+        }
+    }
+}
+
+// Turns resolved statements into bytecode for the VM. It runs after the
+// resolver, so scoping mistakes have already been reported; what is left are
+// the limits of the instruction format.
+#[derive(Debug)]
+pub struct Compiler {
+    // The function being compiled is last; the ones it is nested in come before it.
+    functions: Vec<FunctionState>,
+    // Line of the last token seen, for instructions with no token of their own.
+    line: usize,
+    errors: Vec<String>
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            functions: vec![FunctionState::new(FunctionKind::Script, "", 0)],
+            line: 1,
+            errors: Vec::new()
+        }
+    }
+
+    pub fn compile(mut self, statements: &[Stmt]) -> Result<Function, Vec<String>> {
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.emit_return();
+        let state = self.functions.pop().unwrap();
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        return Ok(Function { name: state.name, arity: state.arity, chunk: state.chunk, upvalues: state.upvalues });
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expression { expression } => {
+                self.expression(expression);
+                self.emit_result();
+                self.emit(OpCode::Pop);
+            },
+            Stmt::Print { expression } => {
+                self.expression(expression);
+                self.emit(OpCode::Print);
+                self.emit_clear_result();
+            },
+            Stmt::Var { name, initializer, slot: _ } => {
+                self.line = name.line;
+                self.expression(initializer);
+                self.emit_result();
+                self.declare_variable(name);
+                self.define_variable(name);
+            },
+            Stmt::Function { declaration, slot: _, captures: _ } => {
+                // Declared before the body so the function can call itself.
+                self.declare_variable(&declaration.name);
+                self.function(FunctionKind::Function, declaration);
+                self.define_variable(&declaration.name);
+                self.emit_clear_result();
+            },
+            Stmt::Class { name, superclass, methods, slot: _ } => {
+                self.class(name, superclass.as_ref(), methods);
+                self.emit_clear_result();
+            },
+            Stmt::Block { statements } => {
+                self.emit_clear_result();
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope();
+            },
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.emit_clear_result();
+                self.expression(condition);
+                let then_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.statement(then_branch);
+                let else_jump = self.emit(OpCode::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            },
+            Stmt::While { condition, body } => {
+                self.emit_clear_result();
+                let loop_start = self.current().chunk.code.len();
+                self.expression(condition);
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.statement(body);
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+            },
+            Stmt::ForIn { name, keyword, iterable, body, slot: _ } => {
+                self.line = name.line;
+                self.emit_clear_result();
+                self.begin_scope();
+                self.expression(iterable);
+                self.line = keyword.line;
+                self.emit(OpCode::IterList);
+                // The list and the position in it sit in two hidden locals
+                // whose names can't clash with a real variable.
+                let slot = self.current().locals.len();
+                if slot + 2 > u8::MAX as usize {
+                    self.error(keyword, "Too many local variables in function.");
+                    return;
+                }
+                self.add_local_named(" list");
+                self.emit_constant(LiteralsAst::Number(0.0));
+                self.add_local_named(" position");
+
+                let loop_start = self.current().chunk.code.len();
+                let exit_jump = self.emit(OpCode::IterNext(slot as u8, 0));
+                self.begin_scope();
+                self.add_local(name);
+                self.statement(body);
+                self.end_scope();
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.end_scope();
+            },
+            Stmt::Return { keyword, value } => {
+                self.line = keyword.line;
+                if self.current().kind == FunctionKind::Initializer {
+                    self.emit(OpCode::GetLocal(0));
+                } else {
+                    self.expression(value);
+                }
+                self.emit(OpCode::Return);
+            },
+            Stmt::Null => {
+                self.emit_clear_result();
+            }
+        }
+    }
+
+    fn class(&mut self, name: &Token, superclass: Option<&Expr>, methods: &[Stmt]) {
+        self.line = name.line;
+        let name_constant = self.identifier_constant(&name.lexeme);
+        self.declare_variable(name);
+        self.emit(OpCode::Class(name_constant));
+        self.define_variable(name);
+
+        // A subclass's methods close over a scope holding `super`, the same
+        // way the tree-walker sets it up.
+        if let Some(superclass) = superclass {
+            self.expression(superclass);
+            self.begin_scope();
+            self.add_local_named("super");
+            self.named_variable(name, None);
+            if let Expr::Variable { name: superclass_name, local: _ } = superclass {
+                self.line = superclass_name.line;
+            }
+            self.emit(OpCode::Inherit);
+        }
+
+        self.named_variable(name, None);
+        for method in methods {
+            if let Stmt::Function { declaration, slot: _, captures: _ } = method {
+                let kind = match declaration.name.lexeme.as_str() {
+                    "init" => FunctionKind::Initializer,
+                    _ => FunctionKind::Method
+                };
+                self.function(kind, declaration);
+                let method_constant = self.identifier_constant(&declaration.name.lexeme);
+                self.emit(OpCode::Method(method_constant));
+            }
+        }
+        self.emit(OpCode::Pop);
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    // Compiles a function body into its own chunk and leaves a closure over it
+    // on the stack.
+    fn function(&mut self, kind: FunctionKind, declaration: &FunctionDecl) {
+        let name = &declaration.name;
+        self.functions.push(FunctionState::new(kind, &name.lexeme, declaration.params.len()));
+        self.begin_scope();
+        for param in &declaration.params {
+            self.add_local(param);
+        }
+        for statement in &declaration.body {
+            self.statement(statement);
+        }
+        self.emit_return();
+
+        let state = self.functions.pop().unwrap();
+        let function = Function { name: state.name, arity: state.arity, chunk: state.chunk, upvalues: state.upvalues };
+        let index = self.current_mut().chunk.add_function(function);
+        if index > u16::MAX as usize {
+            self.error(name, "Too many functions in one chunk.");
+            return;
+        }
+        self.line = name.line;
+        self.emit(OpCode::Closure(index as u16));
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        match expression {
+            Expr::Literal { value } => {
+                match value {
+                    LiteralsAst::True => { self.emit(OpCode::True); },
+                    LiteralsAst::False => { self.emit(OpCode::False); },
+                    LiteralsAst::Null => { self.emit(OpCode::Nil); },
+                    value => self.emit_constant(value.clone())
+                }
+            },
+            Expr::Grouping { expression } => {
+                self.expression(expression);
+            },
+            Expr::Unary { operator, right } => {
+                self.expression(right);
+                self.line = operator.line;
+                match operator.tokentype {
+                    TokenType::Minus => self.emit(OpCode::Negate),
+                    _ => self.emit(OpCode::Not)
+                };
+            },
+            Expr::Binary { left, operator, right } => {
+                self.expression(left);
+                self.expression(right);
+                self.line = operator.line;
+                let op = match operator.tokentype {
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::Percent => OpCode::Modulo,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    _ => OpCode::NotEqual
+                };
+                self.emit(op);
+            },
+            Expr::Logical { left, operator, right } => {
+                self.expression(left);
+                self.line = operator.line;
+                if operator.tokentype == TokenType::Or {
+                    let else_jump = self.emit(OpCode::JumpIfFalse(0));
+                    let end_jump = self.emit(OpCode::Jump(0));
+                    self.patch_jump(else_jump);
+                    self.emit(OpCode::Pop);
+                    self.expression(right);
+                    self.patch_jump(end_jump);
+                } else {
+                    let end_jump = self.emit(OpCode::JumpIfFalse(0));
+                    self.emit(OpCode::Pop);
+                    self.expression(right);
+                    self.patch_jump(end_jump);
+                }
+            },
+            Expr::Variable { name, local: _ } => {
+                self.named_variable(name, None);
+            },
+            Expr::Assign { name, value, local: _ } => {
+                self.named_variable(name, Some(value));
+            },
+            Expr::This { keyword, local: _ } => {
+                self.named_variable(keyword, None);
+            },
+            Expr::Super { keyword, method, local: _, this: _ } => {
+                let this = Token::new(TokenType::This, "this", keyword.literal.clone(), keyword.line, keyword.column, keyword.offset);
+                self.named_variable(&this, None);
+                self.named_variable(keyword, None);
+                self.line = method.line;
+                let method_constant = self.identifier_constant(&method.lexeme);
+                self.emit(OpCode::GetSuper(method_constant));
+            },
+            Expr::Call { callee, paren, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                self.line = paren.line;
+                if arguments.len() > u8::MAX as usize {
+                    self.error(paren, "Can't have more than 255 arguments.");
+                    return;
+                }
+                self.emit(OpCode::Call(arguments.len() as u8));
+            },
+            Expr::Get { object, name } => {
+                self.expression(object);
+                self.line = name.line;
+                let name_constant = self.identifier_constant(&name.lexeme);
+                self.emit(OpCode::GetProperty(name_constant));
+            },
+            Expr::Set { object, name, value } => {
+                self.expression(object);
+                self.expression(value);
+                self.line = name.line;
+                let name_constant = self.identifier_constant(&name.lexeme);
+                self.emit(OpCode::SetProperty(name_constant));
+            },
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expression(element);
+                }
+                let count = self.count(elements.len(), "Too many elements in a list literal.");
+                self.emit(OpCode::List(count));
+            },
+            Expr::Map { brace, entries } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.line = brace.line;
+                let count = self.count(entries.len(), "Too many entries in a map literal.");
+                self.emit(OpCode::Map(count));
+            },
+            Expr::Index { object, bracket, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.line = bracket.line;
+                self.emit(OpCode::Index);
+            },
+            Expr::SetIndex { object, bracket, index, value } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.line = bracket.line;
+                self.emit(OpCode::SetIndex);
+            },
+            Expr::Slice { object, bracket, start, end } => {
+                self.expression(object);
+                self.expression(start);
+                self.expression(end);
+                self.line = bracket.line;
+                self.emit(OpCode::Slice);
+            },
+            Expr::Stringify { expression } => {
+                self.expression(expression);
+                self.emit(OpCode::Stringify);
+            },
+            Expr::Null => {
+                self.emit(OpCode::Nil);
+            }
+        }
+    }
+
+    // Reads the variable, or assigns `value` to it, picking a local slot, an
+    // upvalue or a global in that order.
+    fn named_variable(&mut self, name: &Token, value: Option<&Expr>) {
+        if let Some(value) = value {
+            self.expression(value);
+        }
+        self.line = name.line;
+        let top = self.functions.len() - 1;
+        let (get, set) = if let Some(slot) = self.resolve_local(top, &name.lexeme) {
+            (OpCode::GetLocal(slot), OpCode::SetLocal(slot))
+        } else if let Some(index) = self.resolve_upvalue(top, name) {
+            (OpCode::GetUpvalue(index), OpCode::SetUpvalue(index))
+        } else {
+            let constant = self.identifier_constant(&name.lexeme);
+            (OpCode::GetGlobal(constant), OpCode::SetGlobal(constant))
+        };
+        match value {
+            Some(_) => self.emit(set),
+            None => self.emit(get)
+        };
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+        let locals = &self.functions[function].locals;
+        return locals.iter().rposition(|local| local.name == name).map(|slot| slot as u8);
+    }
+
+    // Looks through the enclosing functions, threading an upvalue through
+    // every function between the use and the declaration.
+    fn resolve_upvalue(&mut self, function: usize, name: &Token) -> Option<u8> {
+        if function == 0 {
+            return None;
+        }
+        if let Some(slot) = self.resolve_local(function - 1, &name.lexeme) {
+            self.functions[function - 1].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(function, UpvalueCapture { is_local: true, index: slot }, name);
+        }
+        if let Some(index) = self.resolve_upvalue(function - 1, name) {
+            return self.add_upvalue(function, UpvalueCapture { is_local: false, index: index }, name);
+        }
+        return None;
+    }
+
+    fn add_upvalue(&mut self, function: usize, capture: UpvalueCapture, name: &Token) -> Option<u8> {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(index) = upvalues.iter().position(|upvalue| *upvalue == capture) {
+            return Some(index as u8);
+        }
+        if upvalues.len() > u8::MAX as usize {
+            self.error(name, "Too many closure variables in function.");
+            return Some(0);
+        }
+        upvalues.push(capture);
+        return Some((upvalues.len() - 1) as u8);
+    }
+
+    // Binds the value on top of the stack to a global. Locals need no
+    // instruction: the value is already sitting in the local's slot.
+    fn define_variable(&mut self, name: &Token) {
+        if self.current().scope_depth > 0 {
+            return;
+        }
+        self.line = name.line;
+        let constant = self.identifier_constant(&name.lexeme);
+        self.emit(OpCode::DefineGlobal(constant));
+    }
+
+    // Claims the next stack slot for a local when inside a scope.
+    fn declare_variable(&mut self, name: &Token) {
+        if self.current().scope_depth > 0 {
+            self.line = name.line;
+            self.add_local(name);
+        }
+    }
+
+    fn add_local(&mut self, name: &Token) {
+        if self.current().locals.len() > u8::MAX as usize {
+            self.error(name, "Too many local variables in function.");
+            return;
+        }
+        self.add_local_named(&name.lexeme);
+    }
+
+    fn add_local_named(&mut self, name: &str) {
+        let state = self.current_mut();
+        let depth = state.scope_depth;
+        state.locals.push(Local { name: name.to_string(), depth: depth, is_captured: false });
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    // Drops the scope's locals, moving captured ones off the stack first.
+    fn end_scope(&mut self) {
+        self.current_mut().scope_depth -= 1;
+        loop {
+            let state = self.current();
+            let captured = match state.locals.last() {
+                Some(local) if local.depth > state.scope_depth => local.is_captured,
+                _ => break
+            };
+            self.emit(if captured { OpCode::CloseUpvalue } else { OpCode::Pop });
+            self.current_mut().locals.pop();
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &Symbol) -> u16 {
+        let index = self.current_mut().chunk.add_name(name);
+        if index > u16::MAX as usize {
+            let line = self.line;
+            self.errors.push(format!("[line {}] Error: Too many names in one chunk.", line));
+            return 0;
+        }
+        return index as u16;
+    }
+
+    fn make_constant(&mut self, value: LiteralsAst) -> u16 {
+        let index = self.current_mut().chunk.add_constant(value);
+        if index > u16::MAX as usize {
+            let line = self.line;
+            self.errors.push(format!("[line {}] Error: Too many constants in one chunk.", line));
+            return 0;
+        }
+        return index as u16;
+    }
+
+    fn emit_constant(&mut self, value: LiteralsAst) {
+        let constant = self.make_constant(value);
+        self.emit(OpCode::Constant(constant));
+    }
+
+    fn count(&mut self, count: usize, message: &str) -> u16 {
+        if count > u16::MAX as usize {
+            let line = self.line;
+            self.errors.push(format!("[line {}] Error: {}", line, message));
+            return 0;
+        }
+        return count as u16;
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        let line = self.line;
+        return self.current_mut().chunk.write(op, line);
+    }
+
+    // Falling off the end of a function returns nil, or `this` from an initializer.
+    fn emit_return(&mut self) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.emit(OpCode::Return);
+    }
+
+    // Only top-level statements track a result, so function bodies don't pay for it.
+    fn emit_result(&mut self) {
+        if self.current().kind == FunctionKind::Script {
+            self.emit(OpCode::Result);
+        }
+    }
+
+    fn emit_clear_result(&mut self) {
+        if self.current().kind == FunctionKind::Script {
+            self.emit(OpCode::ClearResult);
+        }
+    }
+
+    // Fills in the distance of a forward jump now that its target is known.
+    fn patch_jump(&mut self, at: usize) {
+        let distance = self.current().chunk.code.len() - at - 1;
+        if distance > u16::MAX as usize {
+            let line = self.line;
+            self.errors.push(format!("[line {}] Error: Too much code to jump over.", line));
+            return;
+        }
+        let distance = distance as u16;
+        let code = &mut self.current_mut().chunk.code;
+        code[at] = match code[at] {
+            OpCode::Jump(_) => OpCode::Jump(distance),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(distance),
+            OpCode::IterNext(slot, _) => OpCode::IterNext(slot, distance),
+            op => op
+        };
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        let distance = self.current().chunk.code.len() - loop_start + 1;
+        if distance > u16::MAX as usize {
+            let line = self.line;
+            self.errors.push(format!("[line {}] Error: Loop body too large.", line));
+            return;
+        }
+        self.emit(OpCode::Loop(distance as u16));
+    }
+
+    fn current(&self) -> &FunctionState {
+        return self.functions.last().unwrap();
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionState {
+        return self.functions.last_mut().unwrap();
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.errors.push(format!("[line {}] Error at '{}': {}", token.line, token.lexeme, message));
+    }
+}
@@ -0,0 +1,239 @@
+#![allow(clippy::needless_return)]
+
+use crate::generate_ast::LiteralsAst;
+use crate::vm::chunk::{Chunk, OpCode};
+use crate::vm::object::Function;
+use std::fmt::Write;
+
+// Lists a function's instructions, then those of every function compiled
+// inside it, one block per function.
+pub fn disassemble(function: &Function) -> String {
+    let mut listing = String::new();
+    disassemble_function(&mut listing, function);
+    return listing;
+}
+
+fn disassemble_function(listing: &mut String, function: &Function) {
+    let _ = writeln!(listing, "== {} ==", function);
+    for offset in 0..function.chunk.code.len() {
+        let _ = writeln!(listing, "{}", disassemble_instruction(&function.chunk, offset));
+    }
+    for nested in &function.chunk.functions {
+        disassemble_function(listing, nested);
+    }
+}
+
+// One instruction: its offset, its source line (`|` when it is the same as the
+// instruction before), its name and its operands, with constants and names
+// shown by value.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> String {
+    let mut line = format!("{:04} ", offset);
+    if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
+        line.push_str("   | ");
+    } else {
+        let _ = write!(line, "{:4} ", chunk.lines[offset]);
+    }
+
+    let op = chunk.code[offset];
+    let name = op_name(&op);
+    let _ = match op {
+        OpCode::Constant(constant) => {
+            write!(line, "{:<16} {:4} '{}'", name, constant, chunk.constants[constant as usize])
+        },
+        OpCode::GetGlobal(constant)
+        | OpCode::DefineGlobal(constant)
+        | OpCode::SetGlobal(constant)
+        | OpCode::GetProperty(constant)
+        | OpCode::SetProperty(constant)
+        | OpCode::GetSuper(constant)
+        | OpCode::Class(constant)
+        | OpCode::Method(constant) => {
+            write!(line, "{:<16} {:4} '{}'", name, constant, chunk.names[constant as usize])
+        },
+        OpCode::GetLocal(operand)
+        | OpCode::SetLocal(operand)
+        | OpCode::GetUpvalue(operand)
+        | OpCode::SetUpvalue(operand)
+        | OpCode::Call(operand) => {
+            write!(line, "{:<16} {:4}", name, operand)
+        },
+        OpCode::List(count) | OpCode::Map(count) => {
+            write!(line, "{:<16} {:4}", name, count)
+        },
+        OpCode::Jump(distance) | OpCode::JumpIfFalse(distance) => {
+            write!(line, "{:<16} {:4} -> {}", name, offset, offset + 1 + distance as usize)
+        },
+        OpCode::Loop(distance) => {
+            write!(line, "{:<16} {:4} -> {}", name, offset, offset + 1 - distance as usize)
+        },
+        OpCode::IterNext(slot, distance) => {
+            write!(line, "{:<16} {:4} -> {}", name, slot, offset + 1 + distance as usize)
+        },
+        OpCode::Closure(index) => {
+            let function = &chunk.functions[index as usize];
+            let _ = write!(line, "{:<16} {:4} {}", name, index, function);
+            // What the closure captures, one line per upvalue.
+            for capture in &function.upvalues {
+                let kind = if capture.is_local { "local" } else { "upvalue" };
+                let _ = write!(line, "\n{:04}    |                     {} {}", offset, kind, capture.index);
+            }
+            Ok(())
+        },
+        _ => write!(line, "{}", name)
+    };
+    return line;
+}
+
+// The value stack, bottom first, as the execution trace prints it.
+pub fn stack_line(stack: &[LiteralsAst]) -> String {
+    let mut line = "          ".to_string();
+    for value in stack {
+        let _ = write!(line, "[ {} ]", value);
+    }
+    return line;
+}
+
+fn op_name(op: &OpCode) -> &'static str {
+    match op {
+        OpCode::Constant(_) => "OP_CONSTANT",
+        OpCode::Nil => "OP_NIL",
+        OpCode::True => "OP_TRUE",
+        OpCode::False => "OP_FALSE",
+        OpCode::Pop => "OP_POP",
+        OpCode::GetLocal(_) => "OP_GET_LOCAL",
+        OpCode::SetLocal(_) => "OP_SET_LOCAL",
+        OpCode::GetGlobal(_) => "OP_GET_GLOBAL",
+        OpCode::DefineGlobal(_) => "OP_DEFINE_GLOBAL",
+        OpCode::SetGlobal(_) => "OP_SET_GLOBAL",
+        OpCode::GetUpvalue(_) => "OP_GET_UPVALUE",
+        OpCode::SetUpvalue(_) => "OP_SET_UPVALUE",
+        OpCode::GetProperty(_) => "OP_GET_PROPERTY",
+        OpCode::SetProperty(_) => "OP_SET_PROPERTY",
+        OpCode::GetSuper(_) => "OP_GET_SUPER",
+        OpCode::Equal => "OP_EQUAL",
+        OpCode::NotEqual => "OP_NOT_EQUAL",
+        OpCode::Greater => "OP_GREATER",
+        OpCode::GreaterEqual => "OP_GREATER_EQUAL",
+        OpCode::Less => "OP_LESS",
+        OpCode::LessEqual => "OP_LESS_EQUAL",
+        OpCode::Add => "OP_ADD",
+        OpCode::Subtract => "OP_SUBTRACT",
+        OpCode::Multiply => "OP_MULTIPLY",
+        OpCode::Divide => "OP_DIVIDE",
+        OpCode::Modulo => "OP_MODULO",
+        OpCode::Not => "OP_NOT",
+        OpCode::Negate => "OP_NEGATE",
+        OpCode::Stringify => "OP_STRINGIFY",
+        OpCode::Print => "OP_PRINT",
+        OpCode::Jump(_) => "OP_JUMP",
+        OpCode::JumpIfFalse(_) => "OP_JUMP_IF_FALSE",
+        OpCode::Loop(_) => "OP_LOOP",
+        OpCode::Call(_) => "OP_CALL",
+        OpCode::Closure(_) => "OP_CLOSURE",
+        OpCode::CloseUpvalue => "OP_CLOSE_UPVALUE",
+        OpCode::Return => "OP_RETURN",
+        OpCode::Class(_) => "OP_CLASS",
+        OpCode::Inherit => "OP_INHERIT",
+        OpCode::Method(_) => "OP_METHOD",
+        OpCode::List(_) => "OP_LIST",
+        OpCode::Map(_) => "OP_MAP",
+        OpCode::Index => "OP_INDEX",
+        OpCode::SetIndex => "OP_SET_INDEX",
+        OpCode::Slice => "OP_SLICE",
+        OpCode::IterList => "OP_ITER_LIST",
+        OpCode::IterNext(_, _) => "OP_ITER_NEXT",
+        OpCode::Result => "OP_RESULT",
+        OpCode::ClearResult => "OP_CLEAR_RESULT"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+    use crate::test_support::Captured;
+    use crate::vm::Vm;
+    use crate::vm::compiler::Compiler;
+
+    fn compile(source: &str) -> Function {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let mut statements = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        return Compiler::new().compile(&statements).unwrap();
+    }
+
+    #[test]
+    fn test_listing_collapses_repeated_lines() {
+        let listing = disassemble(&compile("var a = 1;\nprint a + 2;"));
+        assert_eq!(listing, "\
+== <script> ==
+0000    1 OP_CONSTANT         0 '1'
+0001    | OP_RESULT
+0002    | OP_DEFINE_GLOBAL    0 'a'
+0003    2 OP_GET_GLOBAL       0 'a'
+0004    | OP_CONSTANT         1 '2'
+0005    | OP_ADD
+0006    | OP_PRINT
+0007    | OP_CLEAR_RESULT
+0008    | OP_NIL
+0009    | OP_RETURN
+");
+    }
+
+    #[test]
+    fn test_listing_shows_jumps_captures_and_nested_functions() {
+        let listing = disassemble(&compile("fun outer() {\n  var x = 1;\n  fun inner() { return x; }\n  while (x) x = nil;\n}"));
+        assert_eq!(listing, "\
+== <script> ==
+0000    1 OP_CLOSURE          0 <fn outer>
+0001    | OP_DEFINE_GLOBAL    0 'outer'
+0002    | OP_CLEAR_RESULT
+0003    | OP_NIL
+0004    | OP_RETURN
+== <fn outer> ==
+0000    2 OP_CONSTANT         0 '1'
+0001    3 OP_CLOSURE          0 <fn inner>
+0001    |                     local 1
+0002    4 OP_GET_LOCAL        1
+0003    | OP_JUMP_IF_FALSE    3 -> 9
+0004    | OP_POP
+0005    | OP_NIL
+0006    | OP_SET_LOCAL        1
+0007    | OP_POP
+0008    | OP_LOOP             8 -> 2
+0009    | OP_POP
+0010    | OP_NIL
+0011    | OP_RETURN
+== <fn inner> ==
+0000    3 OP_GET_UPVALUE      0
+0001    | OP_RETURN
+0002    | OP_NIL
+0003    | OP_RETURN
+");
+    }
+
+    #[test]
+    fn test_trace_dumps_the_stack_before_each_instruction() {
+        let trace = Captured::default();
+        let mut vm = Vm::with_outputs(Box::new(Captured::default()), Box::new(trace.clone()));
+        vm.trace_execution = true;
+        vm.interpret(compile("print 1 + 2;")).unwrap();
+        assert_eq!(trace.contents(), "          [ <script> ]
+0000    1 OP_CONSTANT         0 '1'
+          [ <script> ][ 1 ]
+0001    | OP_CONSTANT         1 '2'
+          [ <script> ][ 1 ][ 2 ]
+0002    | OP_ADD
+          [ <script> ][ 3 ]
+0003    | OP_PRINT
+          [ <script> ]
+0004    | OP_CLEAR_RESULT
+          [ <script> ]
+0005    | OP_NIL
+          [ <script> ][ nil ]
+0006    | OP_RETURN
+");
+    }
+}
@@ -0,0 +1,739 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use crate::generate_ast::{self, LiteralsAst};
+use crate::interpreter::{RuntimeError, FRAMES_MAX};
+use crate::map::LoxMap;
+use crate::stdlib::{self, Globals, NativeState};
+use crate::symbol::{self, Symbol};
+use crate::trace::{self, trace};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+pub mod chunk;
+pub mod compiler;
+pub mod debug;
+pub mod object;
+
+use chunk::{Chunk, OpCode};
+use crate::gc::{Heap, Roots};
+use object::{BoundMethod, Class, Closure, Function, Instance, Object, Upvalue};
+
+// A function call in progress. `slots` is where its window of the value stack
+// starts: the callee, then the arguments, then its locals.
+#[derive(Debug)]
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    slots: usize
+}
+
+impl CallFrame {
+    fn chunk(&self) -> &Chunk {
+        return &self.closure.function.chunk;
+    }
+
+    // The line of the instruction being executed.
+    fn line(&self) -> usize {
+        return self.chunk().lines[self.ip - 1];
+    }
+
+    fn name(&self, index: u16) -> &Symbol {
+        return &self.chunk().names[index as usize];
+    }
+}
+
+// The bytecode backend: runs what `compiler::Compiler` produces on a value
+// stack, with one frame per active call.
+pub struct Vm {
+    stack: Vec<LiteralsAst>,
+    frames: Vec<CallFrame>,
+    pub globals: HashMap<Symbol, LiteralsAst>,
+    // Upvalues still pointing into the stack, so closures created in the same
+    // scope share them.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // The value of the last top-level statement run.
+    result: LiteralsAst,
+    pub heap: Heap,
+    pub natives: NativeState,
+    output: Box<dyn Write>,
+    error_output: Box<dyn Write>,
+    // Debugging aids, both written to the error sink: a listing of each
+    // script's bytecode before it runs, and the stack and instruction at
+    // every step while it does.
+    pub print_code: bool,
+    pub trace_execution: bool
+}
+
+impl fmt::Debug for Vm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vm")
+            .field("stack", &self.stack)
+            .field("globals", &self.globals.keys())
+            .finish()
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Globals for Vm {
+    fn define_global(&mut self, name: &str, value: LiteralsAst) {
+        self.globals.insert(Symbol::intern(name), value);
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        return Vm::with_outputs(Box::new(io::stdout()), Box::new(io::stderr()));
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        return Vm::with_outputs(output, Box::new(io::stderr()));
+    }
+
+    pub fn with_outputs(output: Box<dyn Write>, error_output: Box<dyn Write>) -> Self {
+        trace!("New Vm");
+        let mut vm = Self {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            result: LiteralsAst::Null,
+            heap: Heap::new(),
+            natives: NativeState::default(),
+            output: output,
+            error_output: error_output,
+            print_code: false,
+            trace_execution: trace::enabled()
+        };
+        stdlib::register(&mut vm);
+        return vm;
+    }
+
+    // Writes a diagnostic to the error sink, one per line.
+    pub fn report(&mut self, error: &dyn fmt::Display) {
+        let _ = writeln!(self.error_output, "{}", error);
+        let _ = self.error_output.flush();
+    }
+
+    // Runs a compiled script, returning the value of its last top-level
+    // statement. Globals survive into the next call, even after an error.
+    pub fn interpret(&mut self, function: Function) -> Result<LiteralsAst, RuntimeError> {
+        if self.print_code {
+            let _ = write!(self.error_output, "{}", debug::disassemble(&function));
+        }
+        let closure = Rc::new(Closure { function: Rc::new(function), upvalues: Vec::new() });
+        self.stack.push(LiteralsAst::Object(Object::Closure(Rc::clone(&closure))));
+        self.result = LiteralsAst::Null;
+
+        let frame = CallFrame { closure: closure, ip: 0, slots: 0 };
+        let outcome = self.run(frame);
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        outcome?;
+        return Ok(std::mem::replace(&mut self.result, LiteralsAst::Null));
+    }
+
+    // The dispatch loop. The running frame is kept out of `frames`, which
+    // only holds its callers.
+    fn run(&mut self, mut frame: CallFrame) -> Result<(), RuntimeError> {
+        loop {
+            if self.trace_execution {
+                let _ = writeln!(self.error_output, "{}", debug::stack_line(&self.stack));
+                let _ = writeln!(self.error_output, "{}", debug::disassemble_instruction(frame.chunk(), frame.ip));
+            }
+            // Between instructions everything live is somewhere the collector
+            // can see it.
+            if self.heap.should_collect() {
+                self.collect_garbage(&frame);
+            }
+            let op = frame.chunk().code[frame.ip];
+            frame.ip += 1;
+            match op {
+                OpCode::Constant(constant) => {
+                    let value = frame.chunk().constants[constant as usize].clone();
+                    self.stack.push(value);
+                },
+                OpCode::Nil => self.stack.push(LiteralsAst::Null),
+                OpCode::True => self.stack.push(LiteralsAst::True),
+                OpCode::False => self.stack.push(LiteralsAst::False),
+                OpCode::Pop => {
+                    self.pop();
+                },
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[frame.slots + slot as usize].clone();
+                    self.stack.push(value);
+                },
+                OpCode::SetLocal(slot) => {
+                    let value = self.peek(0).clone();
+                    self.stack[frame.slots + slot as usize] = value;
+                },
+                OpCode::GetGlobal(constant) => {
+                    let name = frame.name(constant);
+                    match self.globals.get(name) {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.stack.push(value);
+                        },
+                        None => return Err(error(&frame, format!("Undefined variable '{}'.", name)))
+                    }
+                },
+                OpCode::DefineGlobal(constant) => {
+                    let value = self.pop();
+                    self.globals.insert(frame.name(constant).clone(), value);
+                },
+                OpCode::SetGlobal(constant) => {
+                    let value = self.peek(0).clone();
+                    let name = frame.name(constant);
+                    match self.globals.get_mut(name) {
+                        Some(global) => *global = value,
+                        None => return Err(error(&frame, format!("Undefined variable '{}'.", name)))
+                    }
+                },
+                OpCode::GetUpvalue(index) => {
+                    let value = match &*frame.closure.upvalues[index as usize].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone()
+                    };
+                    self.stack.push(value);
+                },
+                OpCode::SetUpvalue(index) => {
+                    let value = self.peek(0).clone();
+                    match &mut *frame.closure.upvalues[index as usize].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value
+                    };
+                },
+                OpCode::GetProperty(constant) => {
+                    let instance = match self.pop() {
+                        LiteralsAst::Object(Object::Instance(instance)) => instance,
+                        _ => return Err(error(&frame, "Only instances have properties.".to_string()))
+                    };
+                    let name = frame.name(constant);
+                    // Fields shadow methods.
+                    let field = instance.borrow().fields.get(name).cloned();
+                    let value = match field {
+                        Some(value) => value,
+                        None => {
+                            let class = Rc::clone(&instance.borrow().class);
+                            match bind_method(&class, name, LiteralsAst::Object(Object::Instance(instance))) {
+                                Some(method) => {
+                                    self.heap.track(&method);
+                                    method
+                                },
+                                None => return Err(error(&frame, format!("Undefined property '{}'.", name)))
+                            }
+                        }
+                    };
+                    self.stack.push(value);
+                },
+                OpCode::SetProperty(constant) => {
+                    let value = self.pop();
+                    let instance = match self.pop() {
+                        LiteralsAst::Object(Object::Instance(instance)) => instance,
+                        _ => return Err(error(&frame, "Only instances have fields.".to_string()))
+                    };
+                    instance.borrow_mut().fields.insert(frame.name(constant).clone(), value.clone());
+                    self.stack.push(value);
+                },
+                OpCode::GetSuper(constant) => {
+                    let superclass = match self.pop() {
+                        LiteralsAst::Object(Object::Class(class)) => class,
+                        _ => return Err(error(&frame, "Can't use 'super' outside of a class.".to_string()))
+                    };
+                    let receiver = self.pop();
+                    let name = frame.name(constant);
+                    match bind_method(&superclass, name, receiver) {
+                        Some(method) => {
+                            self.heap.track(&method);
+                            self.stack.push(method);
+                        },
+                        None => return Err(error(&frame, format!("Undefined property '{}'.", name)))
+                    }
+                },
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(boolean(LiteralsAst::is_equal(&left, &right)));
+                },
+                OpCode::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(boolean(!LiteralsAst::is_equal(&left, &right)));
+                },
+                OpCode::Greater => {
+                    let (left, right) = self.numbers(&frame)?;
+                    self.stack.push(boolean(left > right));
+                },
+                OpCode::GreaterEqual => {
+                    let (left, right) = self.numbers(&frame)?;
+                    self.stack.push(boolean(left >= right));
+                },
+                OpCode::Less => {
+                    let (left, right) = self.numbers(&frame)?;
+                    self.stack.push(boolean(left < right));
+                },
+                OpCode::LessEqual => {
+                    let (left, right) = self.numbers(&frame)?;
+                    self.stack.push(boolean(left <= right));
+                },
+                OpCode::Add => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = match (left, right) {
+                        (LiteralsAst::Number(left), LiteralsAst::Number(right)) => LiteralsAst::Number(left + right),
+                        (LiteralsAst::Strings(left), LiteralsAst::Strings(right)) => {
+                            LiteralsAst::Strings([&*left, &*right].concat().into())
+                        },
+                        _ => return Err(error(&frame, "Operands must be two numbers or two strings.".to_string()))
+                    };
+                    self.stack.push(value);
+                },
+                OpCode::Subtract => {
+                    let (left, right) = self.numbers(&frame)?;
+                    self.stack.push(LiteralsAst::Number(left - right));
+                },
+                OpCode::Multiply => {
+                    let (left, right) = self.numbers(&frame)?;
+                    self.stack.push(LiteralsAst::Number(left * right));
+                },
+                OpCode::Divide => {
+                    let (left, right) = self.numbers(&frame)?;
+                    self.stack.push(LiteralsAst::Number(left / right));
+                },
+                OpCode::Modulo => {
+                    let (left, right) = self.numbers(&frame)?;
+                    self.stack.push(LiteralsAst::Number(left % right));
+                },
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(boolean(!value.is_truthy()));
+                },
+                OpCode::Negate => {
+                    match self.pop() {
+                        LiteralsAst::Number(n) => self.stack.push(LiteralsAst::Number(-n)),
+                        _ => return Err(error(&frame, "Operand must be a number.".to_string()))
+                    }
+                },
+                OpCode::Stringify => {
+                    let value = match self.pop() {
+                        LiteralsAst::Strings(value) => value,
+                        value => value.to_string().into()
+                    };
+                    self.stack.push(LiteralsAst::Strings(value));
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    // A closed pipe or full disk shouldn't abort the script.
+                    let _ = writeln!(self.output, "{}", value);
+                },
+                OpCode::Jump(distance) => {
+                    frame.ip += distance as usize;
+                },
+                OpCode::JumpIfFalse(distance) => {
+                    if !self.peek(0).is_truthy() {
+                        frame.ip += distance as usize;
+                    }
+                },
+                OpCode::Loop(distance) => {
+                    frame.ip -= distance as usize;
+                },
+                OpCode::Call(count) => {
+                    if let Some(callee) = self.call_value(count as usize, &frame)? {
+                        self.frames.push(std::mem::replace(&mut frame, callee));
+                    }
+                },
+                OpCode::Closure(index) => {
+                    let function = Rc::clone(&frame.chunk().functions[index as usize]);
+                    let mut upvalues = Vec::with_capacity(function.upvalues.len());
+                    for capture in &function.upvalues {
+                        if capture.is_local {
+                            upvalues.push(self.capture_upvalue(frame.slots + capture.index as usize));
+                        } else {
+                            upvalues.push(Rc::clone(&frame.closure.upvalues[capture.index as usize]));
+                        }
+                    }
+                    let closure = LiteralsAst::Object(Object::Closure(Rc::new(Closure { function: function, upvalues: upvalues })));
+                    self.heap.track(&closure);
+                    self.stack.push(closure);
+                },
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                },
+                OpCode::Return => {
+                    let value = self.pop();
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            frame = caller;
+                            self.stack.push(value);
+                        },
+                        None => return Ok(())
+                    }
+                },
+                OpCode::Class(constant) => {
+                    let class = Class { name: frame.name(constant).to_string(), methods: HashMap::new() };
+                    let class = LiteralsAst::Object(Object::Class(Rc::new(RefCell::new(class))));
+                    self.heap.track(&class);
+                    self.stack.push(class);
+                },
+                OpCode::Inherit => {
+                    let subclass = self.pop();
+                    let (LiteralsAst::Object(Object::Class(superclass)), LiteralsAst::Object(Object::Class(subclass))) = (self.peek(0), subclass) else {
+                        return Err(error(&frame, "Superclass must be a class.".to_string()));
+                    };
+                    let methods = superclass.borrow().methods.clone();
+                    subclass.borrow_mut().methods.extend(methods);
+                },
+                OpCode::Method(constant) => {
+                    let method = self.pop();
+                    if let (LiteralsAst::Object(Object::Closure(method)), LiteralsAst::Object(Object::Class(class))) = (method, self.peek(0)) {
+                        class.borrow_mut().methods.insert(frame.name(constant).clone(), method);
+                    }
+                },
+                OpCode::List(count) => {
+                    let items = self.stack.split_off(self.stack.len() - count as usize);
+                    let list = LiteralsAst::List(Rc::new(RefCell::new(items)));
+                    self.heap.track(&list);
+                    self.stack.push(list);
+                },
+                OpCode::Map(count) => {
+                    let entries = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                    let mut map = LoxMap::new();
+                    for entry in entries.chunks(2) {
+                        let key = generate_ast::map_key(&entry[0]).map_err(|message| error(&frame, message))?;
+                        map.insert(key, entry[1].clone());
+                    }
+                    let map = LiteralsAst::Map(Rc::new(RefCell::new(map)));
+                    self.heap.track(&map);
+                    self.stack.push(map);
+                },
+                OpCode::Index => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let value = generate_ast::index_value(&object, &index).map_err(|message| error(&frame, message))?;
+                    self.stack.push(value);
+                },
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    let value = generate_ast::set_index_value(&object, &index, value).map_err(|message| error(&frame, message))?;
+                    self.stack.push(value);
+                },
+                OpCode::Slice => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    let object = self.pop();
+                    let value = generate_ast::slice_value(&object, &start, &end).map_err(|message| error(&frame, message))?;
+                    self.heap.track(&value);
+                    self.stack.push(value);
+                },
+                OpCode::IterList => {
+                    if !matches!(self.peek(0), LiteralsAst::List(_)) {
+                        return Err(error(&frame, "Can only iterate over a list.".to_string()));
+                    }
+                },
+                OpCode::IterNext(slot, distance) => {
+                    let list = frame.slots + slot as usize;
+                    let position = match self.stack[list + 1] {
+                        LiteralsAst::Number(position) => position as usize,
+                        _ => 0
+                    };
+                    // The length is read on every pass, so a body that pushes
+                    // to the list also walks the new elements.
+                    let item = match &self.stack[list] {
+                        LiteralsAst::List(items) => items.borrow().get(position).cloned(),
+                        _ => None
+                    };
+                    match item {
+                        Some(item) => {
+                            self.stack[list + 1] = LiteralsAst::Number((position + 1) as f64);
+                            self.stack.push(item);
+                        },
+                        None => frame.ip += distance as usize
+                    }
+                },
+                OpCode::Result => {
+                    self.result = self.peek(0).clone();
+                },
+                OpCode::ClearResult => {
+                    self.result = LiteralsAst::Null;
+                }
+            }
+        }
+    }
+
+    // Starts a call to the value sitting below its `count` arguments. A Lox
+    // function gets a new frame for the caller to switch to; anything else
+    // runs to completion here and leaves its result on the stack.
+    fn call_value(&mut self, count: usize, frame: &CallFrame) -> Result<Option<CallFrame>, RuntimeError> {
+        let callee_slot = self.stack.len() - count - 1;
+        match self.stack[callee_slot].clone() {
+            LiteralsAst::Object(Object::Closure(closure)) => self.call_closure(closure, count, frame),
+            LiteralsAst::Object(Object::BoundMethod(bound)) => {
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call_closure(Rc::clone(&bound.method), count, frame)
+            },
+            LiteralsAst::Object(Object::Class(class)) => {
+                let instance = LiteralsAst::Object(Object::Instance(Rc::new(RefCell::new(Instance::new(Rc::clone(&class))))));
+                self.heap.track(&instance);
+                self.stack[callee_slot] = instance;
+                let initializer = class.borrow().methods.get(&symbol::init()).cloned();
+                match initializer {
+                    Some(initializer) => self.call_closure(initializer, count, frame),
+                    None if count != 0 => Err(error(frame, format!("Expected 0 arguments but got {}.", count))),
+                    None => Ok(None)
+                }
+            },
+            LiteralsAst::Native(native) => {
+                if count != native.arity {
+                    return Err(error(frame, format!("Expected {} arguments but got {}.", native.arity, count)));
+                }
+                // Natives don't know where they were called from, so their
+                // errors point at the call.
+                let result = (native.function)(&mut self.natives, &self.stack[callee_slot + 1..])
+                    .map_err(|err| error(frame, err.message))?;
+                // Lists and maps a native built are the VM's to manage now.
+                self.heap.adopt(&result);
+                self.stack.truncate(callee_slot);
+                self.stack.push(result);
+                Ok(None)
+            },
+            _ => Err(error(frame, "Can only call functions and classes.".to_string()))
+        }
+    }
+
+    fn call_closure(&mut self, closure: Rc<Closure>, count: usize, frame: &CallFrame) -> Result<Option<CallFrame>, RuntimeError> {
+        if count != closure.function.arity {
+            return Err(error(frame, format!("Expected {} arguments but got {}.", closure.function.arity, count)));
+        }
+        if self.frames.len() + 1 >= FRAMES_MAX {
+            return Err(error(frame, "Stack overflow.".to_string()));
+        }
+        let slots = self.stack.len() - count - 1;
+        return Ok(Some(CallFrame { closure: closure, ip: 0, slots: slots }));
+    }
+
+    // Reuses the open upvalue for `slot` if a closure already captured it.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if let Upvalue::Open(open) = *upvalue.borrow() {
+                if open == slot {
+                    return Rc::clone(upvalue);
+                }
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.heap.track_upvalue(&upvalue);
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        return upvalue;
+    }
+
+    // Moves every captured variable at or above `from` off the stack and
+    // into its upvalue.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= from => slot,
+                _ => return true
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+
+    // Everything the running program can reach starts from here: the stack,
+    // globals, the result register, open upvalues and every active frame.
+    fn collect_garbage(&mut self, frame: &CallFrame) {
+        let mut roots = Roots::default();
+        for value in self.stack.iter().chain(self.globals.values()) {
+            roots.value(value);
+        }
+        roots.value(&self.result);
+        for upvalue in &self.open_upvalues {
+            roots.upvalue(upvalue);
+        }
+        for caller in &self.frames {
+            roots.closure(&caller.closure);
+        }
+        roots.closure(&frame.closure);
+
+        let collection = self.heap.collect(roots);
+        if self.heap.log {
+            let _ = writeln!(self.error_output, "-- gc: collected {} bytes (from {} to {}) next at {}",
+                collection.freed(), collection.before, collection.after, collection.next_gc);
+        }
+    }
+
+    fn pop(&mut self) -> LiteralsAst {
+        return self.stack.pop().unwrap();
+    }
+
+    fn peek(&self, distance: usize) -> &LiteralsAst {
+        return &self.stack[self.stack.len() - 1 - distance];
+    }
+
+    // Pops the two operands of an arithmetic or comparison operator.
+    fn numbers(&mut self, frame: &CallFrame) -> Result<(f64, f64), RuntimeError> {
+        match (self.peek(1), self.peek(0)) {
+            (LiteralsAst::Number(left), LiteralsAst::Number(right)) => {
+                let operands = (*left, *right);
+                self.stack.truncate(self.stack.len() - 2);
+                Ok(operands)
+            },
+            _ => Err(error(frame, "Operands must be numbers.".to_string()))
+        }
+    }
+}
+
+fn bind_method(class: &Rc<RefCell<Class>>, name: &Symbol, receiver: LiteralsAst) -> Option<LiteralsAst> {
+    let method = class.borrow().methods.get(name).cloned()?;
+    return Some(LiteralsAst::Object(Object::BoundMethod(Rc::new(BoundMethod { receiver: receiver, method: method }))));
+}
+
+fn boolean(value: bool) -> LiteralsAst {
+    if value {
+        return LiteralsAst::True;
+    }
+    return LiteralsAst::False;
+}
+
+// A runtime error reported against the line of the instruction being run.
+fn error(frame: &CallFrame, message: String) -> RuntimeError {
+    return RuntimeError::at_line(frame.line(), message);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::Captured;
+    use crate::{Backend, Lox, Value, STACK_SIZE};
+    use std::thread;
+
+    // Runs `source` on one backend, returning what it printed followed by
+    // the error that stopped it, if any.
+    fn run(backend: Backend, source: &str) -> String {
+        return run_with(backend, source, false);
+    }
+
+    fn run_with(backend: Backend, source: &str, gc_stress: bool) -> String {
+        let output = Captured::default();
+        let mut lox = Lox::with_backend(backend, Box::new(output.clone()), Box::new(Captured::default()));
+        lox.set_gc_stress(gc_stress);
+        let result = lox.run_source(source);
+        let mut printed = output.contents();
+        if let Err(err) = result {
+            printed.push_str(&err.to_string());
+        }
+        return printed;
+    }
+
+    fn assert_same(source: &str) {
+        let expected = run(Backend::TreeWalker, source);
+        assert_eq!(run(Backend::Vm, source), expected, "for program:\n{}", source);
+    }
+
+    const PROGRAMS: [&str; 44] = [
+        "print 1 + 2 * 3 - 4 / 2; print 7 % 3; print -(2); print !nil; print !0;",
+        "print \"a\" + \"b\"; print 1 == 1; print \"a\" != \"a\"; print nil == false; print 1 < 2 and 3 >= 3;",
+        "print nil or \"x\"; print 0 and 1; print \"\" or 2;",
+        "var a = 1; { var a = 2; print a; } print a; a = 3; print a;",
+        "var i = 0; while (i < 3) { print i; i = i + 1; }",
+        "for (var i = 0; i < 3; i = i + 1) print i * i;",
+        "if (1 > 2) print \"yes\"; else print \"no\"; if (nil) print 1;",
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(15);",
+        "fun f() {} print f(); print f; print clock;",
+        "fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; } var c = counter(); c(); print c();",
+        "var fs = []; for (var x in [1, 2, 3]) { fun f() { return x; } push(fs, f); } for (var f in fs) print f();",
+        "var get; var set; { var shared = 1; fun g() { return shared; } fun s(v) { shared = v; } get = g; set = s; } set(5); print get();",
+        "{ fun outer() { var a = \"a\"; fun middle() { fun inner() { return a; } return inner; } return middle; } print outer()()(); }",
+        "class A { init(n) { this.n = n; } twice() { return this.n * 2; } } var a = A(4); print a.twice(); print a; print A;",
+        "class A { hi() { return \"A\"; } } class B < A { hi() { return \"B\" + super.hi(); } } print B().hi();",
+        "class A { init() { this.x = 1; return; } } var a = A(); print a.init() == a; print a.x;",
+        "class A { m() { return this; } } var a = A(); var m = a.m; print m() == a; a.f = m; print a.f() == a;",
+        "var l = [1, \"two\", [3]]; print l; print l[2][0]; l[0] = 9; print l; print l[1:]; print len(l);",
+        "var l = [1]; for (var x in l) { if (x < 4) push(l, x + 1); print x; } print pop(l);",
+        "var l = [1]; push(l, l); print l; print l == l; var k = [1]; push(k, k); print l == k; print [l];",
+        "var m = {\"a\": 1, 2: true}; print m; print m[\"a\"]; m[nil] = 3; print keys(m); print has(m, 2);",
+        "var m = {}; m[\"self\"] = m; print m; print m == m; var n = {}; n[\"self\"] = n; print m == n; print [m];",
+        "var name = \"lox\"; print \"hi ${name}, ${1 + 2} ${[1]}\";",
+        "print \"hello\"[1]; print \"hello\"[1:3]; print upper(\"abc\"); print split(\"a,b\", \",\");",
+        "var a = 1; a;",
+        "print undefined;",
+        "var x = 1; x = y;",
+        "print 1 + nil;",
+        "print -\"a\";",
+        "print 1 < \"a\";",
+        "nil();",
+        "fun f(a) {} f(1, 2);",
+        "class A {} A(1);",
+        "print 1.x;",
+        "var a = 1; a.x = 2;",
+        "fun f() { print \"called\"; return 1; } var a = 1; a.x = f();",
+        "class A {} print A().missing;",
+        "var A = 1; class B < A {}",
+        "print [1][5];",
+        "print {}[\"k\"];",
+        "for (var x in 5) print x;",
+        "print sqrt(\"a\");",
+        "var m = {[1]: 2};",
+        "fun f() { return g(); }\nfun g() {\n  return 1 + nil;\n}\nf();",
+    ];
+
+    #[test]
+    fn test_matches_tree_walker() {
+        for program in PROGRAMS {
+            assert_same(program);
+        }
+    }
+
+    // Collecting at every allocation must never free anything still in use.
+    #[test]
+    fn test_matches_tree_walker_under_gc_stress() {
+        for program in PROGRAMS {
+            assert_eq!(run_with(Backend::Vm, program, true), run(Backend::TreeWalker, program), "for program:\n{}", program);
+        }
+    }
+
+    #[test]
+    fn test_returns_value_of_last_statement() {
+        let sources = ["var a = 2; a * 21;", "print 1;", "{ 1; 2; }", "if (false) 1;", "var i = 0; while (i < 3) i = i + 1;", "fun f() {}"];
+        for source in sources {
+            let mut tree_walker = Lox::with_backend(Backend::TreeWalker, Box::new(Captured::default()), Box::new(Captured::default()));
+            let mut vm = Lox::with_backend(Backend::Vm, Box::new(Captured::default()), Box::new(Captured::default()));
+            assert_eq!(vm.run_source(source).unwrap(), tree_walker.run_source(source).unwrap(), "for {}", source);
+        }
+    }
+
+    #[test]
+    fn test_globals_persist_after_an_error() {
+        let output = Captured::default();
+        let mut lox = Lox::with_backend(Backend::Vm, Box::new(output.clone()), Box::new(Captured::default()));
+        assert!(lox.run_source("var a = 1; nil();").is_err());
+        lox.run_source("fun f() { return a + 1; } print f();").unwrap();
+        assert_eq!(output.contents(), "2\n");
+        assert_eq!(lox.get_global("a"), Some(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn test_runaway_recursion_is_an_error() {
+        let source = "fun f(n) { if (n == 0) return 0; return f(n - 1) + 1; }\nprint f(4094);\nf(4095);";
+        // The tree-walker needs the big stack to get as deep as the VM.
+        let session = thread::Builder::new().stack_size(STACK_SIZE)
+            .spawn(move || (run(Backend::TreeWalker, source), run(Backend::Vm, source)))
+            .unwrap();
+        let (tree_walker, vm) = session.join().unwrap();
+        assert_eq!(vm, "4094\nStack overflow.\n[line 1]");
+        assert_eq!(tree_walker, vm);
+    }
+}
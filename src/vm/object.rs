@@ -0,0 +1,167 @@
+#![allow(clippy::redundant_field_names)]
+
+use crate::generate_ast::LiteralsAst;
+use crate::symbol::Symbol;
+use crate::vm::chunk::Chunk;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::ptr;
+use std::rc::Rc;
+
+// The values only the VM makes. They travel inside `LiteralsAst::Object` so
+// the lists, maps and natives both backends share can hold them, but only the
+// VM ever looks inside one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
+    Class(Rc<RefCell<Class>>),
+    Instance(Rc<RefCell<Instance>>),
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Closure(closure) => write!(f, "{}", closure),
+            Object::BoundMethod(method) => write!(f, "{}", method),
+            Object::Class(class) => write!(f, "{}", class.borrow()),
+            Object::Instance(instance) => write!(f, "{}", instance.borrow()),
+        }
+    }
+}
+
+// Where a closure finds a captured variable when it is created: a local slot
+// of the function around it, or one of that function's own upvalues.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueCapture {
+    pub is_local: bool,
+    pub index: u8,
+}
+
+// A compiled function body. The script itself is one too, with an empty name.
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueCapture>,
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            return write!(f, "<script>");
+        }
+        write!(f, "<fn {}>", self.name)
+    }
+}
+
+// A captured variable. It points at its stack slot while the declaring
+// function is still running and holds the value itself once that returns.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(LiteralsAst),
+}
+
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+// Upvalues can point back at the closure, so they are left out.
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Closure")
+            .field("function", &self.function.name)
+            .finish()
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+// Methods are copied down from the superclass when a subclass is declared,
+// so a lookup never has to walk the chain.
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub methods: HashMap<Symbol, Rc<Closure>>,
+}
+
+impl PartialEq for Class {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub struct Instance {
+    pub class: Rc<RefCell<Class>>,
+    pub fields: HashMap<Symbol, LiteralsAst>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<RefCell<Class>>) -> Self {
+        Self {
+            class: class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+// Fields can point back at the instance, so only their names are printed.
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Instance")
+            .field("class", &self.class.borrow().name)
+            .field("fields", &self.fields.keys())
+            .finish()
+    }
+}
+
+impl PartialEq for Instance {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.borrow().name)
+    }
+}
+
+// A method read off an instance, remembering the instance it came from.
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: LiteralsAst,
+    pub method: Rc<Closure>,
+}
+
+impl PartialEq for BoundMethod {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for BoundMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.method)
+    }
+}